@@ -0,0 +1,317 @@
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+use crate::error::CanisterError;
+
+// Length of a hash160 (P2PKH, P2SH and P2WPKH payloads)
+const HASH160_LEN: usize = 20;
+// Length of a sha256 (P2WSH payload) or of an x-only public key (P2TR payload)
+const HASH256_LEN: usize = 32;
+
+/// Standard output types an address can encode.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressType {
+    P2pkh,
+    P2sh,
+    P2wpkh,
+    P2wsh,
+    P2tr,
+}
+
+/// Address encoding parameters of a family of Bitcoin networks.
+pub struct AddressParams {
+    pub name: &'static str,
+    p2pkh_prefix: u8,
+    p2sh_prefix: u8,
+    hrp: &'static str,
+}
+
+pub const MAINNET_PARAMS: AddressParams = AddressParams {
+    name: "mainnet",
+    p2pkh_prefix: 0x00,
+    p2sh_prefix: 0x05,
+    hrp: "bc",
+};
+
+// Shared by testnet, testnet4 and signet
+pub const TESTNET_PARAMS: AddressParams = AddressParams {
+    name: "testnet",
+    p2pkh_prefix: 0x6f,
+    p2sh_prefix: 0xc4,
+    hrp: "tb",
+};
+
+pub const REGTEST_PARAMS: AddressParams = AddressParams {
+    name: "regtest",
+    p2pkh_prefix: 0x6f,
+    p2sh_prefix: 0xc4,
+    hrp: "bcrt",
+};
+
+const KNOWN_PARAMS: [&AddressParams; 3] = [&MAINNET_PARAMS, &TESTNET_PARAMS, &REGTEST_PARAMS];
+
+/// Parses `address` and checks that it belongs to the network of `params`.
+/// Returns the type of output the address encodes.
+pub fn validate_address(
+    address: &str,
+    params: &AddressParams,
+) -> Result<AddressType, CanisterError> {
+    let lowercase = address.to_lowercase();
+    let segwit_params = KNOWN_PARAMS
+        .iter()
+        .find(|known| lowercase.starts_with(&format!("{}1", known.hrp)));
+
+    match segwit_params {
+        Some(found) => {
+            let address_type = parse_segwit(address)?;
+            check_network(found, params)?;
+            Ok(address_type)
+        }
+        None => parse_base58(address, params),
+    }
+}
+
+/// Returns `address` in the form Maestro indexes it. BIP-173 also allows
+/// bech32 addresses in uppercase, which are lowercased here; anything else,
+/// including invalid mixed-case bech32, is returned unchanged for
+/// `validate_address` to check.
+pub fn canonical_address(address: &str) -> String {
+    let lowercase = address.to_lowercase();
+    let is_segwit = KNOWN_PARAMS
+        .iter()
+        .any(|known| lowercase.starts_with(&format!("{}1", known.hrp)));
+
+    if is_segwit && address == address.to_uppercase() {
+        lowercase
+    } else {
+        address.to_string()
+    }
+}
+
+fn check_network(found: &AddressParams, expected: &AddressParams) -> Result<(), CanisterError> {
+    if found.name != expected.name {
+        return Err(CanisterError::AddressNetworkMismatch {
+            expected: expected.name.to_string(),
+            found: found.name.to_string(),
+        });
+    }
+    Ok(())
+}
+
+// Checksums are verified with bech32 for witness version 0 and bech32m above,
+// as per BIP-350
+fn parse_segwit(address: &str) -> Result<AddressType, CanisterError> {
+    let (_, version, program) = bech32::segwit::decode(address)
+        .map_err(|e| CanisterError::InvalidAddress(e.to_string()))?;
+
+    match (version.to_u8(), program.len()) {
+        (0, HASH160_LEN) => Ok(AddressType::P2wpkh),
+        (0, HASH256_LEN) => Ok(AddressType::P2wsh),
+        (1, HASH256_LEN) => Ok(AddressType::P2tr),
+        (version, len) => Err(CanisterError::InvalidAddress(format!(
+            "unsupported witness program: version {}, {} bytes",
+            version, len
+        ))),
+    }
+}
+
+fn parse_base58(address: &str, params: &AddressParams) -> Result<AddressType, CanisterError> {
+    let payload = bs58::decode(address)
+        .with_check(None)
+        .into_vec()
+        .map_err(|e| CanisterError::InvalidAddress(e.to_string()))?;
+    if payload.len() != 1 + HASH160_LEN {
+        return Err(CanisterError::InvalidAddress(format!(
+            "invalid base58 payload length {}",
+            payload.len()
+        )));
+    }
+
+    let prefix = payload[0];
+    if prefix == params.p2pkh_prefix {
+        return Ok(AddressType::P2pkh);
+    }
+    if prefix == params.p2sh_prefix {
+        return Ok(AddressType::P2sh);
+    }
+
+    match KNOWN_PARAMS
+        .iter()
+        .find(|known| prefix == known.p2pkh_prefix || prefix == known.p2sh_prefix)
+    {
+        Some(found) => Err(CanisterError::AddressNetworkMismatch {
+            expected: params.name.to_string(),
+            found: found.name.to_string(),
+        }),
+        None => Err(CanisterError::InvalidAddress(format!(
+            "unknown version byte {:#04x}",
+            prefix
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_mismatch(address: &str, params: &AddressParams, found: &str) {
+        assert_eq!(
+            validate_address(address, params),
+            Err(CanisterError::AddressNetworkMismatch {
+                expected: params.name.to_string(),
+                found: found.to_string(),
+            })
+        );
+    }
+
+    fn assert_invalid(address: &str) {
+        for params in KNOWN_PARAMS {
+            assert!(matches!(
+                validate_address(address, params),
+                Err(CanisterError::InvalidAddress(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn mainnet_addresses() {
+        let valid = [
+            ("1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2", AddressType::P2pkh),
+            ("3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy", AddressType::P2sh),
+            // BIP-173
+            (
+                "BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4",
+                AddressType::P2wpkh,
+            ),
+            (
+                "bc1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3qccfmv3",
+                AddressType::P2wsh,
+            ),
+            // BIP-350
+            (
+                "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0",
+                AddressType::P2tr,
+            ),
+        ];
+        for (address, address_type) in valid {
+            assert_eq!(validate_address(address, &MAINNET_PARAMS), Ok(address_type));
+        }
+    }
+
+    #[test]
+    fn testnet_addresses() {
+        let valid = [
+            ("mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn", AddressType::P2pkh),
+            ("2MzQwSSnBHWHqSAqtTVQ6v47XtaisrJa1Vc", AddressType::P2sh),
+            // BIP-173
+            (
+                "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx",
+                AddressType::P2wpkh,
+            ),
+            (
+                "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7",
+                AddressType::P2wsh,
+            ),
+            // BIP-350
+            (
+                "tb1pqqqqp399et2xygdj5xreqhjjvcmzhxw4aywxecjdzew6hylgvsesf3hn0c",
+                AddressType::P2tr,
+            ),
+        ];
+        for (address, address_type) in valid {
+            assert_eq!(validate_address(address, &TESTNET_PARAMS), Ok(address_type));
+        }
+    }
+
+    #[test]
+    fn regtest_addresses() {
+        let valid = [
+            // Regtest shares its base58 prefixes with testnet
+            ("mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn", AddressType::P2pkh),
+            ("2MzQwSSnBHWHqSAqtTVQ6v47XtaisrJa1Vc", AddressType::P2sh),
+            (
+                "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080",
+                AddressType::P2wpkh,
+            ),
+            (
+                "bcrt1q09uhj7te09uhj7te09uhj7te09uhj7te09uhj7te09uhj7te09usesl055",
+                AddressType::P2wsh,
+            ),
+            (
+                "bcrt1p09uhj7te09uhj7te09uhj7te09uhj7te09uhj7te09uhj7te09usn8lxvg",
+                AddressType::P2tr,
+            ),
+        ];
+        for (address, address_type) in valid {
+            assert_eq!(validate_address(address, &REGTEST_PARAMS), Ok(address_type));
+        }
+    }
+
+    #[test]
+    fn wrong_network() {
+        assert_mismatch(
+            "1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2",
+            &TESTNET_PARAMS,
+            "mainnet",
+        );
+        assert_mismatch(
+            "mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn",
+            &MAINNET_PARAMS,
+            "testnet",
+        );
+        assert_mismatch(
+            "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0",
+            &TESTNET_PARAMS,
+            "mainnet",
+        );
+        assert_mismatch(
+            "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx",
+            &REGTEST_PARAMS,
+            "testnet",
+        );
+        assert_mismatch(
+            "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080",
+            &MAINNET_PARAMS,
+            "regtest",
+        );
+    }
+
+    #[test]
+    fn wrong_checksum_variant() {
+        // Witness version 1 with a bech32 checksum (BIP-350)
+        assert_invalid("bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqh2y7hd");
+        // Witness version 0 with a bech32m checksum (BIP-350)
+        assert_invalid("bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kemeawh");
+        assert_invalid("tb1q0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vq24jc47");
+    }
+
+    #[test]
+    fn mixed_case() {
+        // BIP-173, valid once lowercased
+        assert_invalid("tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sL5k7");
+    }
+
+    #[test]
+    fn canonical_addresses() {
+        // BIP-173
+        let uppercase = "BC1QW508D6QEJXTDG4Y5R3ZARVARY0C5XW7KV8F3T4";
+        let canonical = canonical_address(uppercase);
+        assert_eq!(canonical, uppercase.to_lowercase());
+        assert_eq!(
+            validate_address(&canonical, &MAINNET_PARAMS),
+            Ok(AddressType::P2wpkh)
+        );
+
+        // Base58 is case sensitive, and mixed case is left for validation to reject
+        let mixed_case = "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sL5k7";
+        for address in ["1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2", mixed_case] {
+            assert_eq!(canonical_address(address), address);
+        }
+        assert_invalid(&canonical_address(mixed_case));
+    }
+
+    #[test]
+    fn bad_base58_checksum() {
+        assert_invalid("1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN3");
+    }
+}
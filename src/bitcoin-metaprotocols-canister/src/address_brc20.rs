@@ -0,0 +1,281 @@
+use candid::{candid_method, CandidType};
+use ic_cdk_macros::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::address::{canonical_address, validate_address, AddressType};
+use crate::auth::Role;
+use crate::common::LastUpdated;
+use crate::config::config;
+use crate::error::CanisterError;
+use crate::maestro_client::MaestroClient;
+
+// Transfer inscriptions listed per address, the largest page Maestro serves
+const MAX_TRANSFER_INSCRIPTIONS: u32 = 100;
+
+// BRC-20 amounts have at most 18 decimals
+const BRC20_DECIMALS: usize = 18;
+
+// Most tickers an address is expected to hold balances of. Addresses holding
+// more fail to fetch.
+const MAX_BRC20_TICKERS: u32 = 1000;
+
+// Upper bound on the size of one balance in a Maestro response, a ticker and
+// two amounts at full precision
+const MAX_BRC20_BALANCE_BYTES: u64 = 150;
+
+// Maestro BRC-20 balance and transfer inscription types
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+pub struct MaestroBrc20Balance {
+    pub total: String,
+    pub available: String,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+pub struct MaestroAddressBrc20Response {
+    // Balances keyed by ticker
+    pub data: BTreeMap<String, MaestroBrc20Balance>,
+    pub last_updated: LastUpdated,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+pub struct MaestroBrc20TransferInscription {
+    pub inscription_id: String,
+    pub ticker: String,
+    pub amount: String,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+pub struct MaestroBrc20TransferableResponse {
+    pub data: Vec<MaestroBrc20TransferInscription>,
+    pub last_updated: LastUpdated,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+pub struct Brc20Balance {
+    pub ticker: String,
+    pub overall: String,
+    pub available: String,
+    // Locked in transfer inscriptions that have not been sent yet, none if
+    // Maestro reported inconsistent balances
+    pub transferable: Option<String>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+pub struct Brc20TransferInscription {
+    pub inscription_id: String,
+    pub ticker: String,
+    pub amount: String,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+pub struct AddressBrc20 {
+    pub address_type: AddressType,
+    pub balances: Vec<Brc20Balance>,
+    pub transfer_inscriptions: Vec<Brc20TransferInscription>,
+    // True if the address holds more than `MAX_TRANSFER_INSCRIPTIONS` transfer inscriptions
+    pub transfer_inscriptions_truncated: bool,
+    pub last_updated: LastUpdated,
+}
+
+// Parses a decimal amount into units of 10^-18
+fn parse_brc20_amount(amount: &str) -> Option<u128> {
+    let (integer, fraction) = amount.split_once('.').unwrap_or((amount, ""));
+    if integer.is_empty()
+        || fraction.len() > BRC20_DECIMALS
+        || !integer
+            .chars()
+            .chain(fraction.chars())
+            .all(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+
+    let integer: u128 = integer.parse().ok()?;
+    let fraction: u128 = format!("{:0<width$}", fraction, width = BRC20_DECIMALS)
+        .parse()
+        .ok()?;
+    integer
+        .checked_mul(10u128.pow(BRC20_DECIMALS as u32))?
+        .checked_add(fraction)
+}
+
+fn format_brc20_amount(amount: u128) -> String {
+    let scale = 10u128.pow(BRC20_DECIMALS as u32);
+    let integer = amount / scale;
+    let fraction = format!("{:0>width$}", amount % scale, width = BRC20_DECIMALS);
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.is_empty() {
+        integer.to_string()
+    } else {
+        format!("{}.{}", integer, fraction)
+    }
+}
+
+// Overall minus available, none if Maestro reported amounts we cannot parse
+fn transferable_amount(balance: &MaestroBrc20Balance) -> Option<String> {
+    let overall = parse_brc20_amount(&balance.total)?;
+    let available = parse_brc20_amount(&balance.available)?;
+    Some(format_brc20_amount(overall.checked_sub(available)?))
+}
+
+/// Fetches the first `MAX_TRANSFER_INSCRIPTIONS` transfer inscriptions of an
+/// address that have not been sent yet.
+pub async fn fetch_transfer_inscriptions(
+    client: &MaestroClient,
+    address: &str,
+) -> Result<MaestroBrc20TransferableResponse, CanisterError> {
+    client
+        .get_json_list(
+            &format!(
+                "/addresses/{}/brc20/transferable",
+                urlencoding::encode(address)
+            ),
+            &[],
+            MAX_TRANSFER_INSCRIPTIONS,
+        )
+        .await
+}
+
+#[update]
+#[candid_method(update)]
+pub async fn get_address_brc20(address: String) -> Result<AddressBrc20, CanisterError> {
+    crate::authorize(Role::Reader)?;
+
+    let address = canonical_address(&address);
+    let address_type = validate_address(&address, config().network.address_params())?;
+
+    let client = MaestroClient::new();
+
+    let balances_maestro_response: MaestroAddressBrc20Response = client
+        .get_json_collection(
+            &format!("/addresses/{}/brc20", urlencoding::encode(&address)),
+            &[],
+            MAX_BRC20_TICKERS,
+            MAX_BRC20_BALANCE_BYTES,
+        )
+        .await?;
+    let transferable_maestro_response = fetch_transfer_inscriptions(&client, &address).await?;
+
+    let balances = balances_maestro_response
+        .data
+        .into_iter()
+        .map(|(ticker, balance)| {
+            let transferable = transferable_amount(&balance);
+            if transferable.is_none() {
+                ic_cdk::println!(
+                    "Invalid {} balance of {}: {} overall, {} available",
+                    ticker,
+                    address,
+                    balance.total,
+                    balance.available
+                );
+            }
+            Brc20Balance {
+                ticker,
+                overall: balance.total,
+                available: balance.available,
+                transferable,
+            }
+        })
+        .collect();
+
+    let transfer_inscriptions = transferable_maestro_response
+        .data
+        .into_iter()
+        .map(|inscription| Brc20TransferInscription {
+            inscription_id: inscription.inscription_id,
+            ticker: inscription.ticker,
+            amount: inscription.amount,
+        })
+        .collect();
+
+    Ok(AddressBrc20 {
+        address_type,
+        balances,
+        transfer_inscriptions,
+        transfer_inscriptions_truncated: transferable_maestro_response.next_cursor.is_some(),
+        last_updated: balances_maestro_response.last_updated,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ONE: u128 = 1_000_000_000_000_000_000;
+
+    fn balance(total: &str, available: &str) -> MaestroBrc20Balance {
+        MaestroBrc20Balance {
+            total: total.to_string(),
+            available: available.to_string(),
+        }
+    }
+
+    #[test]
+    fn parse_integer() {
+        assert_eq!(parse_brc20_amount("0"), Some(0));
+        assert_eq!(parse_brc20_amount("42"), Some(42 * ONE));
+        assert_eq!(parse_brc20_amount("42."), Some(42 * ONE));
+    }
+
+    #[test]
+    fn parse_full_precision() {
+        assert_eq!(parse_brc20_amount("0.000000000000000001"), Some(1));
+        assert_eq!(
+            parse_brc20_amount("1.123456789012345678"),
+            Some(ONE + 123_456_789_012_345_678)
+        );
+    }
+
+    #[test]
+    fn parse_rejects_invalid_amounts() {
+        assert_eq!(parse_brc20_amount("0.0000000000000000001"), None);
+        assert_eq!(parse_brc20_amount(""), None);
+        assert_eq!(parse_brc20_amount(".5"), None);
+        assert_eq!(parse_brc20_amount("-1"), None);
+        assert_eq!(parse_brc20_amount("1.2.3"), None);
+        assert_eq!(parse_brc20_amount("1e3"), None);
+    }
+
+    #[test]
+    fn format_trims_fraction() {
+        assert_eq!(format_brc20_amount(0), "0");
+        assert_eq!(format_brc20_amount(42 * ONE), "42");
+        assert_eq!(format_brc20_amount(ONE / 2), "0.5");
+        assert_eq!(format_brc20_amount(1), "0.000000000000000001");
+    }
+
+    #[test]
+    fn amounts_round_trip() {
+        for amount in ["0", "1", "0.5", "21000000", "1.123456789012345678"] {
+            assert_eq!(
+                format_brc20_amount(parse_brc20_amount(amount).unwrap()),
+                amount
+            );
+        }
+    }
+
+    #[test]
+    fn transferable_is_overall_minus_available() {
+        assert_eq!(
+            transferable_amount(&balance("100.5", "40.25")),
+            Some("60.25".to_string())
+        );
+        assert_eq!(
+            transferable_amount(&balance("100", "100")),
+            Some("0".to_string())
+        );
+    }
+
+    #[test]
+    fn transferable_rejects_bad_balances() {
+        assert_eq!(transferable_amount(&balance("40", "100")), None);
+        assert_eq!(transferable_amount(&balance("abc", "0")), None);
+        assert_eq!(
+            transferable_amount(&balance("1", "0.0000000000000000001")),
+            None
+        );
+    }
+}
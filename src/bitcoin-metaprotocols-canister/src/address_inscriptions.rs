@@ -1,15 +1,26 @@
 use candid::{candid_method, CandidType};
-use ic_cdk::api::canister_self;
-use ic_cdk::management_canister::{
-    http_request, HttpHeader, HttpMethod, HttpRequestArgs, TransformContext, TransformFunc,
-};
+use futures::stream::{self, StreamExt};
 use ic_cdk_macros::*;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
 
-use crate::common::{
-    check_authorization, LastUpdated, MaestroCollectionStatsResponse,
-    MaestroInscriptionInfoResponse, MaestroOmbColorGroup, BASE_URL,
-};
+use crate::address::{canonical_address, validate_address, AddressParams, AddressType};
+use crate::auth::Role;
+use crate::common::LastUpdated;
+use crate::config::config;
+use crate::enrichment::{enrich_inscriptions, InscriptionEnrichment};
+use crate::error::CanisterError;
+use crate::maestro_client::MaestroClient;
+use crate::validation::validate_count;
+
+// Largest page Maestro serves for address inscriptions
+const MAX_PAGE_SIZE: u32 = 100;
+
+// Upper bound on the inscriptions returned by `get_all_address_inscriptions`
+const MAX_INSCRIPTIONS_LIMIT: u32 = 1000;
+
+// Upper bound on the addresses looked up by `get_addresses_inscriptions`
+const MAX_ADDRESSES: usize = 20;
 
 // Address-specific types
 #[derive(CandidType, Deserialize, Serialize, Debug)]
@@ -47,205 +58,275 @@ pub struct AddressInscription {
 
 #[derive(CandidType, Deserialize, Serialize, Debug)]
 pub struct AddressInscriptions {
+    pub address_type: AddressType,
     pub data: Vec<AddressInscription>,
     pub last_updated: LastUpdated,
     pub next_cursor: Option<String>,
 }
 
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+pub struct GetAddressInscriptionsArgs {
+    pub address: String,
+    // Between 1 and `MAX_PAGE_SIZE`
+    pub count: u32,
+    // `next_cursor` of the previous page, none for the first page
+    pub cursor: Option<String>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+pub struct GetAllAddressInscriptionsArgs {
+    pub address: String,
+    // Defaults to and is capped at `MAX_INSCRIPTIONS_LIMIT`
+    pub max_inscriptions: Option<u32>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+pub struct AllAddressInscriptions {
+    // `next_cursor` is set if the walk stopped before the last page
+    pub inscriptions: AddressInscriptions,
+    // Number of pages fetched from Maestro
+    pub pages: u32,
+    // True if the address holds inscriptions beyond those returned
+    pub truncated: bool,
+}
+
+async fn fetch_address_inscriptions_page(
+    client: &MaestroClient,
+    address: &str,
+    count: u32,
+    cursor: Option<&str>,
+) -> Result<MaestroAddressInscriptionsResponse, CanisterError> {
+    let mut query = Vec::new();
+    if let Some(cursor) = cursor {
+        query.push(("cursor", cursor));
+    }
+
+    client
+        .get_json_list(
+            &format!("/addresses/{}/inscriptions", urlencoding::encode(address)),
+            &query,
+            count,
+        )
+        .await
+}
+
+async fn enrich_address_inscriptions(
+    client: &MaestroClient,
+    inscriptions: Vec<MaestroAddressInscription>,
+) -> Vec<AddressInscription> {
+    let inscription_ids = inscriptions
+        .iter()
+        .map(|inscription| inscription.inscription_id.clone())
+        .collect();
+    let enrichments = enrich_inscriptions(client, inscription_ids, true).await;
+
+    inscriptions
+        .into_iter()
+        .zip(enrichments)
+        .map(|(inscription, enrichment)| address_inscription(inscription, enrichment))
+        .collect()
+}
+
+fn address_inscription(
+    inscription: MaestroAddressInscription,
+    enrichment: InscriptionEnrichment,
+) -> AddressInscription {
+    AddressInscription {
+        inscription_id: inscription.inscription_id,
+        satoshis: inscription.satoshis,
+        utxo_sat_offset: inscription.utxo_sat_offset,
+        utxo_txid: inscription.utxo_txid,
+        utxo_vout: inscription.utxo_vout,
+        utxo_block_height: inscription.utxo_block_height,
+        utxo_confirmations: inscription.utxo_confirmations,
+        collection_symbol: enrichment.collection_symbol,
+        floor_price: enrichment.floor_price,
+        omb_color: enrichment.omb_color,
+        omb_floor_price: enrichment.omb_floor_price,
+    }
+}
+
 #[update]
 #[candid_method(update)]
 pub async fn get_address_inscriptions(
-    address: String,
-    count: String,
-) -> Result<AddressInscriptions, String> {
-    check_authorization()?;
-
-    let api_key = crate::get_api_key()?;
-
-    let address_inscriptions_maestro_url = format!(
-        "{}/addresses/{}/inscriptions?count={}",
-        BASE_URL, address, count
-    );
-
-    let address_inscriptions_maestro_request = HttpRequestArgs {
-        url: address_inscriptions_maestro_url,
-        method: HttpMethod::GET,
-        headers: vec![HttpHeader {
-            name: "api-key".to_string(),
-            value: api_key.clone(),
-        }],
-        body: None,
-        max_response_bytes: Some(5 * 1000), // 5000 KB
-        transform: Some(TransformContext {
-            function: TransformFunc::new(canister_self(), "transform".to_string()),
-            context: vec![],
-        }),
-    };
+    args: GetAddressInscriptionsArgs,
+) -> Result<AddressInscriptions, CanisterError> {
+    crate::authorize(Role::Reader)?;
 
-    match http_request(&address_inscriptions_maestro_request).await {
-        Ok(response) => {
-            let raw_body = String::from_utf8_lossy(&response.body);
-            ic_cdk::println!("HTTP response body: {}", raw_body);
-
-            let address_inscriptions_maestro_response: MaestroAddressInscriptionsResponse =
-                serde_json::from_slice(&response.body)
-                    .map_err(|e| format!("Failed to parse: {} (body: {})", e, raw_body))?;
-
-            let mut final_result: Vec<AddressInscription> = Vec::new();
-
-            for inscription in address_inscriptions_maestro_response.data {
-                // Fetch inscription info
-                let inscription_info_url = format!(
-                    "{}/assets/inscriptions/{}",
-                    BASE_URL, inscription.inscription_id
-                );
-
-                let inscription_info_request = HttpRequestArgs {
-                    url: inscription_info_url,
-                    method: HttpMethod::GET,
-                    headers: vec![HttpHeader {
-                        name: "api-key".to_string(),
-                        value: api_key.clone(),
-                    }],
-                    body: None,
-                    max_response_bytes: Some(5 * 1000),
-                    transform: Some(TransformContext {
-                        function: TransformFunc::new(canister_self(), "transform".to_string()),
-                        context: vec![],
-                    }),
-                };
-
-                let collection_symbol = match http_request(&inscription_info_request).await {
-                    Ok(inscription_info_response) => {
-                        match serde_json::from_slice::<MaestroInscriptionInfoResponse>(
-                            &inscription_info_response.body,
-                        ) {
-                            Ok(info_response) => info_response.data.collection_symbol,
-                            Err(e) => {
-                                ic_cdk::println!(
-                                    "Failed to parse MaestroInscriptionInfoResponse: {} (body: {})",
-                                    e,
-                                    String::from_utf8_lossy(&inscription_info_response.body)
-                                );
-                                None
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        ic_cdk::println!(
-                            "Failed to fetch inscription info for {}: {:?}",
-                            inscription.inscription_id,
-                            e
-                        );
-                        None
-                    }
-                };
-
-                // Fetch floor price if collection_symbol exists
-                let mut floor_price = 0;
-                if let Some(ref symbol) = collection_symbol {
-                    let collection_stats_url =
-                        format!("{}/assets/collections/{}/stats", BASE_URL, symbol);
-
-                    let collection_stats_request = HttpRequestArgs {
-                        url: collection_stats_url,
-                        method: HttpMethod::GET,
-                        headers: vec![HttpHeader {
-                            name: "api-key".to_string(),
-                            value: api_key.clone(),
-                        }],
-                        body: None,
-                        max_response_bytes: Some(5 * 1000),
-                        transform: Some(TransformContext {
-                            function: TransformFunc::new(canister_self(), "transform".to_string()),
-                            context: vec![],
-                        }),
-                    };
-
-                    floor_price = match http_request(&collection_stats_request).await {
-                        Ok(collection_stats_response) => {
-                            match serde_json::from_slice::<MaestroCollectionStatsResponse>(
-                                &collection_stats_response.body,
-                            ) {
-                                Ok(stats_response) => stats_response
-                                    .data
-                                    .floor_price
-                                    .unwrap_or("0".to_string())
-                                    .parse::<i64>()
-                                    .unwrap_or(0),
-                                Err(e) => {
-                                    ic_cdk::println!(
-                                "Failed to parse MaestroCollectionStatsResponse: {} (body: {})",
-                                e,
-                                String::from_utf8_lossy(&collection_stats_response.body)
-                            );
-                                    0
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            ic_cdk::println!(
-                                "Failed to fetch collection stats for {}: {:?}",
-                                symbol,
-                                e
-                            );
-                            0
-                        }
-                    };
-                }
-
-                // Fetch OMB color group
-                let omb_color_group_url = format!(
-                    "{}/assets/inscriptions/{}/omb_color_group",
-                    BASE_URL, inscription.inscription_id
-                );
-                let omb_color_group_request = HttpRequestArgs {
-                    url: omb_color_group_url,
-                    method: HttpMethod::GET,
-                    headers: vec![HttpHeader {
-                        name: "api-key".to_string(),
-                        value: api_key.clone(),
-                    }],
-                    body: None,
-                    max_response_bytes: Some(5 * 1000),
-                    transform: Some(TransformContext {
-                        function: TransformFunc::new(canister_self(), "transform".to_string()),
-                        context: vec![],
-                    }),
-                };
-                let (omb_color, omb_floor_price) = match http_request(&omb_color_group_request)
-                    .await
-                {
-                    Ok(omb_response) => {
-                        match serde_json::from_slice::<MaestroOmbColorGroup>(&omb_response.body) {
-                            Ok(omb) => (Some(omb.data.omb_color), Some(omb.data.omb_floor_price)),
-                            Err(_) => (None, None),
-                        }
-                    }
-                    Err(_) => (None, None),
-                };
-
-                final_result.push(AddressInscription {
-                    inscription_id: inscription.inscription_id,
-                    satoshis: inscription.satoshis,
-                    utxo_sat_offset: inscription.utxo_sat_offset,
-                    utxo_txid: inscription.utxo_txid,
-                    utxo_vout: inscription.utxo_vout,
-                    utxo_block_height: inscription.utxo_block_height,
-                    utxo_confirmations: inscription.utxo_confirmations,
-                    collection_symbol,
-                    floor_price,
-                    omb_color,
-                    omb_floor_price,
-                });
-            }
-
-            Ok(AddressInscriptions {
-                data: final_result,
-                last_updated: address_inscriptions_maestro_response.last_updated,
-                next_cursor: address_inscriptions_maestro_response.next_cursor,
-            })
-        }
-        Err(e) => Err(format!("HTTP error: {:?}", e)),
+    let address = canonical_address(&args.address);
+    let address_type = validate_address(&address, config().network.address_params())?;
+    validate_count(args.count, MAX_PAGE_SIZE)?;
+
+    let client = MaestroClient::new();
+
+    let address_inscriptions_maestro_response =
+        fetch_address_inscriptions_page(&client, &address, args.count, args.cursor.as_deref())
+            .await?;
+
+    let final_result =
+        enrich_address_inscriptions(&client, address_inscriptions_maestro_response.data).await;
+
+    Ok(AddressInscriptions {
+        address_type,
+        data: final_result,
+        last_updated: address_inscriptions_maestro_response.last_updated,
+        next_cursor: address_inscriptions_maestro_response.next_cursor,
+    })
+}
+
+/// Fetches the first page of inscriptions of several addresses, e.g. the
+/// ordinals and payment addresses of one wallet. Pages are enriched together,
+/// so that inscriptions and collections are looked up once per call rather
+/// than once per address. An address that fails only fails its own entry.
+#[update]
+#[candid_method(update)]
+pub async fn get_addresses_inscriptions(
+    addresses: Vec<String>,
+    count: u32,
+) -> Result<BTreeMap<String, Result<AddressInscriptions, CanisterError>>, CanisterError> {
+    crate::authorize(Role::Reader)?;
+
+    validate_count(count, MAX_PAGE_SIZE)?;
+    let addresses: BTreeSet<String> = addresses
+        .iter()
+        .map(|address| canonical_address(address))
+        .collect();
+    if addresses.len() > MAX_ADDRESSES {
+        return Err(CanisterError::InvalidInput(format!(
+            "at most {} addresses can be looked up per call",
+            MAX_ADDRESSES
+        )));
+    }
+    let config = config();
+    let address_params = config.network.address_params();
+
+    let client = MaestroClient::new();
+    let client = &client;
+
+    let pages: Vec<_> = stream::iter(addresses)
+        .map(|address| async move {
+            let page = fetch_first_page(client, &address, address_params, count).await;
+            (address, page)
+        })
+        .buffered(config.max_concurrent_outcalls as usize)
+        .collect()
+        .await;
+
+    let inscription_ids = pages
+        .iter()
+        .filter_map(|(_, page)| page.as_ref().ok())
+        .flat_map(|(_, response)| &response.data)
+        .map(|inscription| inscription.inscription_id.clone())
+        .collect();
+    let mut enrichments = enrich_inscriptions(client, inscription_ids, true)
+        .await
+        .into_iter();
+
+    // Pages are consumed in the order their inscriptions were enriched
+    Ok(pages
+        .into_iter()
+        .map(|(address, page)| {
+            let address_inscriptions = page.map(|(address_type, response)| AddressInscriptions {
+                address_type,
+                data: response
+                    .data
+                    .into_iter()
+                    .zip(enrichments.by_ref())
+                    .map(|(inscription, enrichment)| address_inscription(inscription, enrichment))
+                    .collect(),
+                last_updated: response.last_updated,
+                next_cursor: response.next_cursor,
+            });
+            (address, address_inscriptions)
+        })
+        .collect())
+}
+
+async fn fetch_first_page(
+    client: &MaestroClient,
+    address: &str,
+    address_params: &AddressParams,
+    count: u32,
+) -> Result<(AddressType, MaestroAddressInscriptionsResponse), CanisterError> {
+    let address_type = validate_address(address, address_params)?;
+    let page = fetch_address_inscriptions_page(client, address, count, None).await?;
+    Ok((address_type, page))
+}
+
+/// Walks Maestro's cursors until the last page, `max_inscriptions`, or the
+/// `max_outcalls_per_call` budget is reached, whichever comes first. The
+/// budget covers the pages and the enrichment of their inscriptions, counting
+/// every enrichment outcall as if nothing were cached.
+#[update]
+#[candid_method(update)]
+pub async fn get_all_address_inscriptions(
+    args: GetAllAddressInscriptionsArgs,
+) -> Result<AllAddressInscriptions, CanisterError> {
+    crate::authorize(Role::Reader)?;
+
+    let address = canonical_address(&args.address);
+    let max_inscriptions = args.max_inscriptions.unwrap_or(MAX_INSCRIPTIONS_LIMIT);
+    if !(1..=MAX_INSCRIPTIONS_LIMIT).contains(&max_inscriptions) {
+        return Err(CanisterError::InvalidInput(format!(
+            "max_inscriptions must be between 1 and {}",
+            MAX_INSCRIPTIONS_LIMIT
+        )));
     }
+    let config = config();
+    let address_type = validate_address(&address, config.network.address_params())?;
+    let max_outcalls = config.max_outcalls_per_call;
+    // Inscription info, plus the OMB color group and the collection's floor price if enabled
+    let outcalls_per_inscription =
+        1 + u32::from(config.fetch_omb_color_groups) + u32::from(config.fetch_floor_prices);
+
+    let client = MaestroClient::new();
+    let mut inscriptions = Vec::new();
+    let mut seen_cursors = BTreeSet::new();
+    let mut cursor: Option<String> = None;
+    let mut pages = 0;
+    let mut outcalls = 0;
+
+    let last_updated = loop {
+        // Never request more than what is left, of the cap or of the budget after
+        // this page's outcall, so that `next_cursor` does not skip anything
+        let affordable = max_outcalls.saturating_sub(outcalls + 1) / outcalls_per_inscription;
+        let count = (max_inscriptions - inscriptions.len() as u32)
+            .min(MAX_PAGE_SIZE)
+            .min(affordable);
+        let page =
+            fetch_address_inscriptions_page(&client, &address, count, cursor.as_deref()).await?;
+        pages += 1;
+        outcalls += 1 + outcalls_per_inscription * page.data.len() as u32;
+        inscriptions.extend(page.data);
+        cursor = page.next_cursor;
+
+        let Some(ref next_cursor) = cursor else {
+            break page.last_updated;
+        };
+        if !seen_cursors.insert(next_cursor.clone()) {
+            ic_cdk::println!(
+                "Cursor loop while walking inscriptions of {}: {}",
+                address,
+                next_cursor
+            );
+            break page.last_updated;
+        }
+        if inscriptions.len() as u32 >= max_inscriptions
+            || outcalls + 1 + outcalls_per_inscription > max_outcalls
+        {
+            break page.last_updated;
+        }
+    };
+
+    let data = enrich_address_inscriptions(&client, inscriptions).await;
+    Ok(AllAddressInscriptions {
+        inscriptions: AddressInscriptions {
+            address_type,
+            data,
+            last_updated,
+            next_cursor: cursor.clone(),
+        },
+        pages,
+        truncated: cursor.is_some(),
+    })
 }
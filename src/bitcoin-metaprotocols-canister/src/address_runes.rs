@@ -0,0 +1,358 @@
+use candid::{candid_method, CandidType};
+use futures::stream::{self, StreamExt};
+use ic_cdk_macros::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::address::{canonical_address, validate_address, AddressType};
+use crate::auth::Role;
+use crate::common::LastUpdated;
+use crate::config::config;
+use crate::enrichment::{enrich_runes, RuneMarketData};
+use crate::error::CanisterError;
+use crate::maestro_client::MaestroClient;
+
+// UTXOs listed per rune, the largest page Maestro serves
+const MAX_RUNE_UTXOS: u32 = 100;
+
+// Most runes an address is expected to hold, e.g. after many airdrops.
+// Addresses holding more fail to fetch.
+const MAX_RUNES: u32 = 2000;
+
+// Upper bound on the size of one balance in a Maestro response, a rune id and
+// an amount of up to 39 digits
+const MAX_RUNE_BALANCE_BYTES: u64 = 80;
+
+// Maestro rune balance and rune UTXO types
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+pub struct MaestroAddressRunesResponse {
+    // Amount held in base units, keyed by rune id
+    pub data: BTreeMap<String, String>,
+    pub last_updated: LastUpdated,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+pub struct MaestroRuneUtxosResponse {
+    pub data: Vec<MaestroRuneUtxo>,
+    pub last_updated: LastUpdated,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+pub struct MaestroRuneUtxo {
+    pub txid: String,
+    pub vout: u32,
+    pub satoshis: String,
+    pub rune_amount: String,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+pub struct RuneUtxo {
+    pub txid: String,
+    pub vout: u32,
+    pub satoshis: String,
+    // In base units
+    pub amount: String,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+pub struct AddressRune {
+    pub rune_id: String,
+    // In base units
+    pub amount: String,
+    // `amount` divided by 10^divisibility, none if the metadata could not be fetched
+    pub balance: Option<String>,
+    pub spaced_name: Option<String>,
+    pub symbol: Option<String>,
+    pub divisibility: Option<u8>,
+    // None if disabled or if it could not be fetched
+    pub market_data: Option<RuneMarketData>,
+    // Value of `balance` at the floor unit price, rounded down to whole sats
+    pub floor_value_sats: Option<u64>,
+    pub utxos: Vec<RuneUtxo>,
+    // True if the rune is held by more than `MAX_RUNE_UTXOS` UTXOs, or if they could not be fetched
+    pub utxos_truncated: bool,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+pub struct AddressRunes {
+    pub address_type: AddressType,
+    pub data: Vec<AddressRune>,
+    // True if the address holds more runes than the `max_outcalls_per_call`
+    // budget covers, in which case only the first ones by rune id are returned
+    pub truncated: bool,
+    pub last_updated: LastUpdated,
+}
+
+/// Formats an amount in base units as a decimal, e.g. "123450" with a
+/// divisibility of 4 as "12.345".
+fn format_rune_amount(amount: &str, divisibility: u8) -> Option<String> {
+    if amount.is_empty() || !amount.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let divisibility = divisibility as usize;
+    let digits = format!("{:0>width$}", amount, width = divisibility + 1);
+    let (integer, fraction) = digits.split_at(digits.len() - divisibility);
+    let integer = match integer.trim_start_matches('0') {
+        "" => "0",
+        integer => integer,
+    };
+    let fraction = fraction.trim_end_matches('0');
+
+    Some(if fraction.is_empty() {
+        integer.to_string()
+    } else {
+        format!("{}.{}", integer, fraction)
+    })
+}
+
+// Parses a decimal into its digits as an integer and the number of fractional
+// digits, e.g. "12.345" into (12345, 3)
+fn parse_decimal(decimal: &str) -> Option<(u128, u32)> {
+    let (integer, fraction) = decimal.split_once('.').unwrap_or((decimal, ""));
+    if integer.is_empty()
+        || !integer
+            .chars()
+            .chain(fraction.chars())
+            .all(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+
+    let digits: u128 = format!("{}{}", integer, fraction).parse().ok()?;
+    Some((digits, u32::try_from(fraction.len()).ok()?))
+}
+
+/// Values an amount in base units at a floor price per whole rune, in integer
+/// arithmetic so that large balances keep their precision. None if the
+/// inputs do not parse or the value does not fit.
+fn floor_value_sats(amount: &str, divisibility: u8, unit_price_sats: &str) -> Option<u64> {
+    if !amount.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let amount: u128 = amount.parse().ok()?;
+    let (price, price_scale) = parse_decimal(unit_price_sats)?;
+
+    let value = amount.checked_mul(price)?;
+    // A scale past `u128` leaves less than one sat
+    let value = match 10u128.checked_pow(u32::from(divisibility) + price_scale) {
+        Some(scale) => value / scale,
+        None => 0,
+    };
+    u64::try_from(value).ok()
+}
+
+async fn fetch_rune_utxos(
+    client: &MaestroClient,
+    address: &str,
+    rune_id: &str,
+) -> Option<(Vec<RuneUtxo>, bool)> {
+    match client
+        .get_json_list::<MaestroRuneUtxosResponse>(
+            &format!(
+                "/addresses/{}/runes/{}",
+                urlencoding::encode(address),
+                urlencoding::encode(rune_id)
+            ),
+            &[],
+            MAX_RUNE_UTXOS,
+        )
+        .await
+    {
+        Ok(utxos_response) => {
+            let utxos = utxos_response
+                .data
+                .into_iter()
+                .map(|utxo| RuneUtxo {
+                    txid: utxo.txid,
+                    vout: utxo.vout,
+                    satoshis: utxo.satoshis,
+                    amount: utxo.rune_amount,
+                })
+                .collect();
+            Some((utxos, utxos_response.next_cursor.is_some()))
+        }
+        Err(e) => {
+            ic_cdk::println!("Failed to fetch {} UTXOs of {}: {}", rune_id, address, e);
+            None
+        }
+    }
+}
+
+/// Lists the runes of an address, with their metadata, market data and UTXOs.
+/// Runes are enriched within the `max_outcalls_per_call` budget, counting
+/// every enrichment outcall as if nothing were cached.
+#[update]
+#[candid_method(update)]
+pub async fn get_address_runes(address: String) -> Result<AddressRunes, CanisterError> {
+    crate::authorize(Role::Reader)?;
+
+    let address = canonical_address(&address);
+    let config = config();
+    let address_type = validate_address(&address, config.network.address_params())?;
+
+    let client = MaestroClient::new();
+
+    let address_runes_maestro_response: MaestroAddressRunesResponse = client
+        .get_json_collection(
+            &format!("/addresses/{}/runes", urlencoding::encode(&address)),
+            &[],
+            MAX_RUNES,
+            MAX_RUNE_BALANCE_BYTES,
+        )
+        .await?;
+
+    // Rune metadata and UTXOs, plus the market data if enabled, after the
+    // outcall for the balances
+    let outcalls_per_rune = 2 + u32::from(config.fetch_rune_market_data);
+    let affordable = (config.max_outcalls_per_call.saturating_sub(1) / outcalls_per_rune) as usize;
+    let truncated = address_runes_maestro_response.data.len() > affordable;
+
+    let balances: Vec<(String, String)> = address_runes_maestro_response
+        .data
+        .into_iter()
+        .take(affordable)
+        .collect();
+    let rune_ids: Vec<String> = balances
+        .iter()
+        .map(|(rune_id, _)| rune_id.clone())
+        .collect();
+    let rune_enrichments = enrich_runes(&client, rune_ids.clone()).await;
+    let rune_utxos: Vec<Option<(Vec<RuneUtxo>, bool)>> = stream::iter(rune_ids)
+        .map(|rune_id| {
+            let client = &client;
+            let address = &address;
+            async move { fetch_rune_utxos(client, address, &rune_id).await }
+        })
+        .buffered(config.max_concurrent_outcalls as usize)
+        .collect()
+        .await;
+
+    let final_result: Vec<AddressRune> =
+        balances
+            .into_iter()
+            .zip(rune_enrichments)
+            .zip(rune_utxos)
+            .map(|(((rune_id, amount), enrichment), utxos)| {
+                let (utxos, utxos_truncated) = utxos.unwrap_or((Vec::new(), true));
+                let metadata = enrichment.metadata;
+                let balance = metadata
+                    .as_ref()
+                    .and_then(|metadata| format_rune_amount(&amount, metadata.divisibility));
+                let unit_price_sats = enrichment
+                    .market_data
+                    .as_ref()
+                    .and_then(|market_data| market_data.floor_unit_price_sats.as_deref());
+                let floor_value_sats = metadata.as_ref().zip(unit_price_sats).and_then(
+                    |(metadata, unit_price_sats)| {
+                        let value =
+                            floor_value_sats(&amount, metadata.divisibility, unit_price_sats);
+                        if value.is_none() {
+                            ic_cdk::println!(
+                                "Failed to value {} {} at {} sats: invalid or too large",
+                                amount,
+                                rune_id,
+                                unit_price_sats
+                            );
+                        }
+                        value
+                    },
+                );
+                AddressRune {
+                    balance,
+                    rune_id,
+                    amount,
+                    spaced_name: metadata
+                        .as_ref()
+                        .map(|metadata| metadata.spaced_name.clone()),
+                    symbol: metadata
+                        .as_ref()
+                        .and_then(|metadata| metadata.symbol.clone()),
+                    divisibility: metadata.map(|metadata| metadata.divisibility),
+                    market_data: enrichment.market_data,
+                    floor_value_sats,
+                    utxos,
+                    utxos_truncated,
+                }
+            })
+            .collect();
+
+    Ok(AddressRunes {
+        address_type,
+        data: final_result,
+        truncated,
+        last_updated: address_runes_maestro_response.last_updated,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_without_divisibility() {
+        assert_eq!(format_rune_amount("0", 0), Some("0".to_string()));
+        assert_eq!(format_rune_amount("12345", 0), Some("12345".to_string()));
+    }
+
+    #[test]
+    fn format_pads_short_amounts() {
+        assert_eq!(format_rune_amount("5", 2), Some("0.05".to_string()));
+        assert_eq!(format_rune_amount("0", 2), Some("0".to_string()));
+    }
+
+    #[test]
+    fn format_trims_zeros() {
+        assert_eq!(format_rune_amount("12300", 2), Some("123".to_string()));
+        assert_eq!(format_rune_amount("12340", 2), Some("123.4".to_string()));
+        assert_eq!(format_rune_amount("000150", 2), Some("1.5".to_string()));
+    }
+
+    #[test]
+    fn format_rejects_non_digits() {
+        assert_eq!(format_rune_amount("", 2), None);
+        assert_eq!(format_rune_amount("1.5", 2), None);
+        assert_eq!(format_rune_amount("-5", 2), None);
+        assert_eq!(format_rune_amount("12a", 0), None);
+    }
+
+    #[test]
+    fn value_at_decimal_price() {
+        // 12.5 runes at 0.4 sats each
+        assert_eq!(floor_value_sats("1250", 2, "0.4"), Some(5));
+        assert_eq!(floor_value_sats("1250", 2, "0.41"), Some(5));
+        assert_eq!(floor_value_sats("7", 0, "3"), Some(21));
+        assert_eq!(floor_value_sats("0", 2, "100"), Some(0));
+    }
+
+    #[test]
+    fn value_keeps_precision_of_large_balances() {
+        // 2^53 + 1 base units, which an f64 rounds to 2^53
+        assert_eq!(
+            floor_value_sats("9007199254740993", 0, "1"),
+            Some(9_007_199_254_740_993)
+        );
+        assert_eq!(
+            floor_value_sats("123456789012345678901234567890", 18, "0.001"),
+            Some(123_456_789)
+        );
+    }
+
+    #[test]
+    fn value_of_tiny_amounts_rounds_down() {
+        assert_eq!(floor_value_sats("1", 38, "0.000001"), Some(0));
+        assert_eq!(floor_value_sats("999", 3, "1"), Some(0));
+    }
+
+    #[test]
+    fn value_rejects_invalid_inputs() {
+        assert_eq!(floor_value_sats("12a", 0, "1"), None);
+        assert_eq!(floor_value_sats("+5", 0, "1"), None);
+        assert_eq!(floor_value_sats("5", 0, "1e3"), None);
+        assert_eq!(floor_value_sats("5", 0, ".5"), None);
+        assert_eq!(floor_value_sats("5", 0, "-1"), None);
+        // Past `u64`
+        assert_eq!(floor_value_sats("100000000000000000000", 0, "1"), None);
+    }
+}
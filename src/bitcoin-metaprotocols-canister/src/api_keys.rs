@@ -0,0 +1,512 @@
+use candid::{candid_method, CandidType, Principal};
+use ic_cdk::api::msg_caller;
+use ic_cdk::management_canister::{http_request, HttpRequestArgs, HttpRequestResult};
+use ic_cdk_macros::*;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use crate::auth::Role;
+use crate::error::CanisterError;
+use crate::memory::{get_memory, Memory, API_KEYS_MEMORY_ID};
+use crate::CallerGuard;
+
+// Name of the key managed through `set_api_key`
+const DEFAULT_KEY_NAME: &str = "default";
+
+// HTTP statuses on which an outcall is retried with the next key
+const FAILOVER_STATUSES: [u16; 3] = [401, 403, 429];
+
+/// Single API key, as stored by the first release.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct ApiKey {
+    key: String,
+}
+
+impl ApiKey {
+    pub fn empty() -> Self {
+        Self { key: String::new() }
+    }
+}
+
+impl Storable for ApiKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(serde_json::to_vec(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    const BOUND: ic_stable_structures::storable::Bound =
+        ic_stable_structures::storable::Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiKeyState {
+    /// Used for outcalls in priority order
+    Active,
+    /// Only used once every active key has been rejected
+    Draining,
+    /// Never used
+    Disabled,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Default)]
+pub struct ApiKeyUsage {
+    pub requests: u64,
+    pub failures: u64,
+    pub last_used_at: Option<u64>,
+    pub last_error: Option<String>,
+    pub last_error_at: Option<u64>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+struct ApiKeyEntry {
+    key: String,
+    // Lower values are tried first
+    priority: u32,
+    state: ApiKeyState,
+    added_by: Option<Principal>,
+    // Nanoseconds since the UNIX epoch
+    added_at: Option<u64>,
+    #[serde(default)]
+    usage: ApiKeyUsage,
+}
+
+impl Storable for ApiKeyEntry {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(serde_json::to_vec(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    const BOUND: ic_stable_structures::storable::Bound =
+        ic_stable_structures::storable::Bound::Unbounded;
+}
+
+/// Summary of the keyring, as seen by the next outcall.
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+pub struct ApiKeyStatus {
+    pub is_set: bool,
+    // Of the key tried first, none if no key is usable
+    pub fingerprint: Option<String>,
+    pub set_by: Option<Principal>,
+    pub set_at: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+pub struct ApiKeyInfo {
+    pub name: String,
+    pub fingerprint: String,
+    pub priority: u32,
+    pub state: ApiKeyState,
+    pub added_by: Option<Principal>,
+    pub added_at: Option<u64>,
+    pub usage: ApiKeyUsage,
+}
+
+thread_local! {
+    // Keyring of Maestro API keys, keyed by name
+    static API_KEYS_STORAGE: RefCell<StableBTreeMap<String, ApiKeyEntry, Memory>> = RefCell::new(
+        StableBTreeMap::init(get_memory(API_KEYS_MEMORY_ID))
+    );
+}
+
+/// Adds the key of the first release as the default key.
+pub fn migrate_api_key(api_key: ApiKey) {
+    if api_key.key.is_empty() {
+        return;
+    }
+
+    API_KEYS_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        if !storage.contains_key(&DEFAULT_KEY_NAME.to_string()) {
+            storage.insert(
+                DEFAULT_KEY_NAME.to_string(),
+                ApiKeyEntry {
+                    key: api_key.key,
+                    priority: 0,
+                    state: ApiKeyState::Active,
+                    added_by: None,
+                    added_at: None,
+                    usage: ApiKeyUsage::default(),
+                },
+            );
+        }
+    });
+}
+
+// Last 4 characters of the key, enough to tell keys apart without revealing them
+fn fingerprint(key: &str) -> String {
+    let chars: Vec<char> = key.chars().collect();
+    chars[chars.len().saturating_sub(4)..].iter().collect()
+}
+
+// Usable keys in the order they should be tried: active keys by priority, then draining keys
+fn candidate_entries() -> Vec<(String, ApiKeyEntry)> {
+    API_KEYS_STORAGE.with(|storage| {
+        let mut entries: Vec<(String, ApiKeyEntry)> = storage
+            .borrow()
+            .iter()
+            .filter(|(_, entry)| entry.state != ApiKeyState::Disabled)
+            .collect();
+        entries.sort_by_key(|(name, entry)| {
+            (
+                entry.state == ApiKeyState::Draining,
+                entry.priority,
+                name.clone(),
+            )
+        });
+        entries
+    })
+}
+
+fn candidate_keys() -> Vec<(String, String)> {
+    candidate_entries()
+        .into_iter()
+        .map(|(name, entry)| (name, entry.key))
+        .collect()
+}
+
+fn record_usage(name: &str, error: Option<String>) {
+    API_KEYS_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        // The key may have been removed while the outcall was in flight
+        let Some(mut entry) = storage.get(&name.to_string()) else {
+            return;
+        };
+
+        let now = ic_cdk::api::time();
+        entry.usage.requests += 1;
+        entry.usage.last_used_at = Some(now);
+        if let Some(error) = error {
+            entry.usage.failures += 1;
+            entry.usage.last_error = Some(error);
+            entry.usage.last_error_at = Some(now);
+        }
+        storage.insert(name.to_string(), entry);
+    });
+}
+
+/// Performs an HTTP outcall authenticated with the keyring. `build_request` is
+/// called with each candidate key in turn, moving on to the next key while
+/// Maestro rejects the current one with 401, 403 or 429. If every key is
+/// rejected, the last response is returned.
+pub async fn http_request_with_failover(
+    build_request: impl Fn(&str) -> HttpRequestArgs,
+) -> Result<HttpRequestResult, CanisterError> {
+    let candidates = candidate_keys();
+    if candidates.is_empty() {
+        return Err(CanisterError::ApiKeyNotSet);
+    }
+
+    let mut last_response = None;
+    for (name, key) in candidates {
+        match http_request(&build_request(&key)).await {
+            Ok(response) => {
+                let status = u16::try_from(&response.status.0).unwrap_or(0);
+                if FAILOVER_STATUSES.contains(&status) {
+                    record_usage(&name, Some(format!("HTTP status {}", status)));
+                    last_response = Some(response);
+                    continue;
+                }
+                record_usage(&name, None);
+                return Ok(response);
+            }
+            Err(e) => {
+                let error = CanisterError::from(e);
+                record_usage(&name, Some(error.to_string()));
+                return Err(error);
+            }
+        }
+    }
+
+    Ok(last_response.expect("at least one candidate key was tried"))
+}
+
+fn validate_key(key: &str) -> Result<(), CanisterError> {
+    if key.is_empty() {
+        return Err(CanisterError::InvalidInput(
+            "API key cannot be empty".to_string(),
+        ));
+    }
+
+    if key.len() > 1000 {
+        return Err(CanisterError::InvalidInput(
+            "API key too long (max 1000 characters)".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn validate_name(name: &str) -> Result<(), CanisterError> {
+    if name.is_empty() {
+        return Err(CanisterError::InvalidInput(
+            "API key name cannot be empty".to_string(),
+        ));
+    }
+
+    if name.len() > 64 {
+        return Err(CanisterError::InvalidInput(
+            "API key name too long (max 64 characters)".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn update_entry(name: String, update: impl FnOnce(&mut ApiKeyEntry)) -> Result<(), CanisterError> {
+    API_KEYS_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        let mut entry = storage
+            .get(&name)
+            .ok_or_else(|| CanisterError::NotFound(format!("API key {}", name)))?;
+        update(&mut entry);
+        storage.insert(name, entry);
+        Ok(())
+    })
+}
+
+#[update]
+#[candid_method(update)]
+async fn set_api_key(new_key: String) -> Result<(), CanisterError> {
+    crate::authorize(Role::Admin)?;
+
+    let caller = msg_caller();
+
+    // Input validation
+    validate_key(&new_key)?;
+
+    // Prevent reentrancy by the same caller
+    let _guard = CallerGuard::new(caller)?;
+
+    // Replaces the default key, keeping its priority and usage counters
+    API_KEYS_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        let previous = storage.get(&DEFAULT_KEY_NAME.to_string());
+        storage.insert(
+            DEFAULT_KEY_NAME.to_string(),
+            ApiKeyEntry {
+                key: new_key,
+                priority: previous.as_ref().map_or(0, |entry| entry.priority),
+                state: ApiKeyState::Active,
+                added_by: Some(caller),
+                added_at: Some(ic_cdk::api::time()),
+                usage: previous.map(|entry| entry.usage).unwrap_or_default(),
+            },
+        );
+    });
+    Ok(())
+}
+
+#[update]
+#[candid_method(update)]
+fn add_api_key(name: String, key: String, priority: u32) -> Result<(), CanisterError> {
+    crate::authorize(Role::Admin)?;
+
+    validate_name(&name)?;
+    validate_key(&key)?;
+
+    API_KEYS_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        if storage.contains_key(&name) {
+            return Err(CanisterError::InvalidInput(format!(
+                "API key {} already exists",
+                name
+            )));
+        }
+        storage.insert(
+            name,
+            ApiKeyEntry {
+                key,
+                priority,
+                state: ApiKeyState::Active,
+                added_by: Some(msg_caller()),
+                added_at: Some(ic_cdk::api::time()),
+                usage: ApiKeyUsage::default(),
+            },
+        );
+        Ok(())
+    })
+}
+
+#[update]
+#[candid_method(update)]
+fn drain_api_key(name: String) -> Result<(), CanisterError> {
+    crate::authorize(Role::Admin)?;
+
+    update_entry(name, |entry| entry.state = ApiKeyState::Draining)
+}
+
+#[update]
+#[candid_method(update)]
+fn disable_api_key(name: String) -> Result<(), CanisterError> {
+    crate::authorize(Role::Admin)?;
+
+    update_entry(name, |entry| entry.state = ApiKeyState::Disabled)
+}
+
+// Makes the key active and tries it before every other key
+#[update]
+#[candid_method(update)]
+fn promote_api_key(name: String) -> Result<(), CanisterError> {
+    crate::authorize(Role::Admin)?;
+
+    promote(name)
+}
+
+// Moves the key ahead of every other one, shifting them back by one so that
+// they keep their relative order
+fn promote(name: String) -> Result<(), CanisterError> {
+    API_KEYS_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        if !storage.contains_key(&name) {
+            return Err(CanisterError::NotFound(format!("API key {}", name)));
+        }
+
+        let entries: Vec<(String, ApiKeyEntry)> = storage.iter().collect();
+        for (entry_name, mut entry) in entries {
+            if entry_name == name {
+                entry.priority = 0;
+                entry.state = ApiKeyState::Active;
+            } else {
+                entry.priority = entry.priority.saturating_add(1);
+            }
+            storage.insert(entry_name, entry);
+        }
+        Ok(())
+    })
+}
+
+#[update]
+#[candid_method(update)]
+fn remove_api_key(name: String) -> Result<(), CanisterError> {
+    crate::authorize(Role::Admin)?;
+
+    API_KEYS_STORAGE.with(|storage| {
+        storage
+            .borrow_mut()
+            .remove(&name)
+            .map(|_| ())
+            .ok_or_else(|| CanisterError::NotFound(format!("API key {}", name)))
+    })
+}
+
+#[query(guard = "crate::admin_guard")]
+#[candid_method(query)]
+fn get_api_key_status() -> ApiKeyStatus {
+    match candidate_entries().into_iter().next() {
+        Some((_, entry)) => ApiKeyStatus {
+            is_set: true,
+            fingerprint: Some(fingerprint(&entry.key)),
+            set_by: entry.added_by,
+            set_at: entry.added_at,
+        },
+        None => ApiKeyStatus {
+            is_set: false,
+            fingerprint: None,
+            set_by: None,
+            set_at: None,
+        },
+    }
+}
+
+#[query(guard = "crate::admin_guard")]
+#[candid_method(query)]
+fn list_api_keys() -> Vec<ApiKeyInfo> {
+    API_KEYS_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(name, entry)| ApiKeyInfo {
+                name,
+                fingerprint: fingerprint(&entry.key),
+                priority: entry.priority,
+                state: entry.state,
+                added_by: entry.added_by,
+                added_at: entry.added_at,
+                usage: entry.usage,
+            })
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert(name: &str, priority: u32, state: ApiKeyState) {
+        API_KEYS_STORAGE.with(|storage| {
+            storage.borrow_mut().insert(
+                name.to_string(),
+                ApiKeyEntry {
+                    key: format!("key-{}", name),
+                    priority,
+                    state,
+                    added_by: None,
+                    added_at: None,
+                    usage: ApiKeyUsage::default(),
+                },
+            )
+        });
+    }
+
+    fn candidate_names() -> Vec<String> {
+        candidate_entries()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect()
+    }
+
+    #[test]
+    fn candidates_are_active_keys_by_priority_then_draining_keys() {
+        insert("draining", 0, ApiKeyState::Draining);
+        insert("disabled", 0, ApiKeyState::Disabled);
+        insert("second", 2, ApiKeyState::Active);
+        insert("first", 1, ApiKeyState::Active);
+        insert("also-second", 2, ApiKeyState::Active);
+
+        assert_eq!(
+            candidate_names(),
+            ["first", "also-second", "second", "draining"]
+        );
+        assert_eq!(
+            candidate_keys()[0],
+            ("first".to_string(), "key-first".to_string())
+        );
+    }
+
+    #[test]
+    fn promote_moves_key_first_and_keeps_the_others_in_order() {
+        insert("a", 0, ApiKeyState::Active);
+        insert("b", 1, ApiKeyState::Active);
+        insert("c", 2, ApiKeyState::Active);
+
+        promote("c".to_string()).unwrap();
+        assert_eq!(candidate_names(), ["c", "a", "b"]);
+    }
+
+    #[test]
+    fn promote_reactivates_the_key() {
+        insert("active", 0, ApiKeyState::Active);
+        insert("draining", 1, ApiKeyState::Draining);
+        insert("disabled", 2, ApiKeyState::Disabled);
+
+        promote("disabled".to_string()).unwrap();
+        assert_eq!(candidate_names(), ["disabled", "active", "draining"]);
+    }
+
+    #[test]
+    fn promote_unknown_key_changes_nothing() {
+        insert("a", 0, ApiKeyState::Active);
+
+        assert!(matches!(
+            promote("b".to_string()),
+            Err(CanisterError::NotFound(_))
+        ));
+        assert_eq!(candidate_entries()[0].1.priority, 0);
+    }
+}
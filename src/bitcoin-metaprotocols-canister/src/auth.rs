@@ -0,0 +1,130 @@
+use candid::{candid_method, CandidType, Principal};
+use ic_cdk_macros::*;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use crate::common::INITIAL_ROLES;
+use crate::error::CanisterError;
+use crate::memory::{get_memory, Memory, ROLES_MEMORY_ID};
+
+/// Access roles, ordered from least to most privileged. Each role includes
+/// the permissions of the roles below it.
+#[derive(
+    CandidType, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord,
+)]
+pub enum Role {
+    /// May only query inscription data
+    Reader,
+    /// May additionally tune the canister configuration
+    Operator,
+    /// May additionally manage roles and secrets
+    Admin,
+}
+
+impl Storable for Role {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(serde_json::to_vec(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    const BOUND: ic_stable_structures::storable::Bound =
+        ic_stable_structures::storable::Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+pub struct RoleAssignment {
+    pub principal: Principal,
+    pub role: Role,
+}
+
+thread_local! {
+    static ROLES_STORAGE: RefCell<StableBTreeMap<Principal, Role, Memory>> = RefCell::new(
+        StableBTreeMap::init(get_memory(ROLES_MEMORY_ID))
+    );
+}
+
+/// Returns true if `principal` holds `required` or a more privileged role.
+/// Canister controllers are always treated as admins.
+pub fn has_role(principal: &Principal, required: Role) -> bool {
+    if ic_cdk::api::is_controller(principal) {
+        return true;
+    }
+    ROLES_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .get(principal)
+            .is_some_and(|role| role >= required)
+    })
+}
+
+/// Seeds the role assignments with the principals from `INITIAL_ROLES`.
+pub fn seed_roles() {
+    ROLES_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        for (caller, role) in INITIAL_ROLES {
+            let principal = Principal::from_text(caller)
+                .unwrap_or_else(|e| ic_cdk::trap(format!("Invalid principal {}: {}", caller, e)));
+            storage.insert(principal, role);
+        }
+    });
+}
+
+#[update]
+#[candid_method(update)]
+pub fn grant_role(principal: Principal, role: Role) -> Result<(), CanisterError> {
+    crate::authorize(Role::Admin)?;
+
+    assign_roles(&[RoleAssignment { principal, role }])
+}
+
+/// Grants each principal its role, replacing any role it had. Nothing is
+/// granted if one of the principals is anonymous.
+pub fn assign_roles(assignments: &[RoleAssignment]) -> Result<(), CanisterError> {
+    if assignments
+        .iter()
+        .any(|assignment| assignment.principal == Principal::anonymous())
+    {
+        return Err(CanisterError::InvalidInput(
+            "The anonymous principal cannot be granted a role".to_string(),
+        ));
+    }
+
+    ROLES_STORAGE.with(|storage| {
+        let mut storage = storage.borrow_mut();
+        for assignment in assignments {
+            storage.insert(assignment.principal, assignment.role);
+        }
+    });
+    Ok(())
+}
+
+#[update]
+#[candid_method(update)]
+pub fn revoke_role(principal: Principal) -> Result<(), CanisterError> {
+    crate::authorize(Role::Admin)?;
+
+    ROLES_STORAGE.with(|storage| {
+        storage
+            .borrow_mut()
+            .remove(&principal)
+            .map(|_| ())
+            .ok_or_else(|| CanisterError::NotFound(format!("{} has no role", principal)))
+    })
+}
+
+#[query(guard = "crate::admin_guard")]
+#[candid_method(query)]
+pub fn list_roles() -> Vec<RoleAssignment> {
+    ROLES_STORAGE.with(|storage| {
+        storage
+            .borrow()
+            .iter()
+            .map(|(principal, role)| RoleAssignment { principal, role })
+            .collect()
+    })
+}
@@ -0,0 +1,622 @@
+use candid::{candid_method, CandidType};
+use ic_cdk_macros::*;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::thread::LocalKey;
+
+use crate::common::{MaestroCollectionStats, MaestroRuneMarketData};
+use crate::config::config;
+use crate::memory::{
+    get_memory, Memory, COLLECTION_STATS_CACHE_MEMORY_ID, INSCRIPTION_INFO_CACHE_MEMORY_ID,
+    INSCRIPTION_INFO_LRU_MEMORY_ID, RUNE_MARKET_DATA_CACHE_MEMORY_ID,
+    RUNE_METADATA_CACHE_MEMORY_ID, RUNE_METADATA_LRU_MEMORY_ID,
+};
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct CachedCollectionStats {
+    pub stats: MaestroCollectionStats,
+    // Block height Maestro reported when the stats were fetched
+    pub block_height: i64,
+    // Nanoseconds since the UNIX epoch
+    pub fetched_at: u64,
+}
+
+impl Storable for CachedCollectionStats {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(serde_json::to_vec(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    const BOUND: ic_stable_structures::storable::Bound =
+        ic_stable_structures::storable::Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct CachedRuneMarketData {
+    pub market_data: MaestroRuneMarketData,
+    // Block height Maestro reported when the market data was fetched
+    pub block_height: i64,
+    // Nanoseconds since the UNIX epoch
+    pub fetched_at: u64,
+}
+
+impl Storable for CachedRuneMarketData {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(serde_json::to_vec(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    const BOUND: ic_stable_structures::storable::Bound =
+        ic_stable_structures::storable::Bound::Unbounded;
+}
+
+/// Inscription info that never changes once an inscription is indexed.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct CachedInscriptionInfo {
+    pub collection_symbol: Option<String>,
+    // Position of the entry in `INSCRIPTION_INFO_LRU`
+    pub last_access: u64,
+}
+
+impl Storable for CachedInscriptionInfo {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(serde_json::to_vec(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    const BOUND: ic_stable_structures::storable::Bound =
+        ic_stable_structures::storable::Bound::Unbounded;
+}
+
+impl LruEntry for CachedInscriptionInfo {
+    fn last_access(&self) -> u64 {
+        self.last_access
+    }
+
+    fn set_last_access(&mut self, last_access: u64) {
+        self.last_access = last_access;
+    }
+}
+
+/// Rune metadata, fixed at etching.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct CachedRuneMetadata {
+    pub spaced_name: String,
+    pub symbol: Option<String>,
+    pub divisibility: u8,
+    // Position of the entry in `RUNE_METADATA_LRU`
+    pub last_access: u64,
+}
+
+impl Storable for CachedRuneMetadata {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(serde_json::to_vec(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    const BOUND: ic_stable_structures::storable::Bound =
+        ic_stable_structures::storable::Bound::Unbounded;
+}
+
+impl LruEntry for CachedRuneMetadata {
+    fn last_access(&self) -> u64 {
+        self.last_access
+    }
+
+    fn set_last_access(&mut self, last_access: u64) {
+        self.last_access = last_access;
+    }
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+pub struct CollectionStatsCacheEntry {
+    pub collection_symbol: String,
+    pub cached: CachedCollectionStats,
+}
+
+thread_local! {
+    // Collection stats keyed by collection symbol
+    static COLLECTION_STATS_CACHE: RefCell<StableBTreeMap<String, CachedCollectionStats, Memory>> = RefCell::new(
+        StableBTreeMap::init(get_memory(COLLECTION_STATS_CACHE_MEMORY_ID))
+    );
+
+    // Rune market data keyed by rune id
+    static RUNE_MARKET_DATA_CACHE: RefCell<StableBTreeMap<String, CachedRuneMarketData, Memory>> = RefCell::new(
+        StableBTreeMap::init(get_memory(RUNE_MARKET_DATA_CACHE_MEMORY_ID))
+    );
+
+    // Inscription info keyed by inscription id
+    static INSCRIPTION_INFO_CACHE: RefCell<StableBTreeMap<String, CachedInscriptionInfo, Memory>> = RefCell::new(
+        StableBTreeMap::init(get_memory(INSCRIPTION_INFO_CACHE_MEMORY_ID))
+    );
+
+    // Inscription ids keyed by last access, least recently used first
+    static INSCRIPTION_INFO_LRU: RefCell<StableBTreeMap<u64, String, Memory>> = RefCell::new(
+        StableBTreeMap::init(get_memory(INSCRIPTION_INFO_LRU_MEMORY_ID))
+    );
+
+    // Rune metadata keyed by rune id
+    static RUNE_METADATA_CACHE: RefCell<StableBTreeMap<String, CachedRuneMetadata, Memory>> = RefCell::new(
+        StableBTreeMap::init(get_memory(RUNE_METADATA_CACHE_MEMORY_ID))
+    );
+
+    // Rune ids keyed by last access, least recently used first
+    static RUNE_METADATA_LRU: RefCell<StableBTreeMap<u64, String, Memory>> = RefCell::new(
+        StableBTreeMap::init(get_memory(RUNE_METADATA_LRU_MEMORY_ID))
+    );
+
+    // Hit and miss counters, reset on upgrade
+    static COLLECTION_STATS_COUNTERS: CacheCounters = const { CacheCounters::new() };
+    static INSCRIPTION_INFO_COUNTERS: CacheCounters = const { CacheCounters::new() };
+    static RUNE_MARKET_DATA_COUNTERS: CacheCounters = const { CacheCounters::new() };
+    static RUNE_METADATA_COUNTERS: CacheCounters = const { CacheCounters::new() };
+}
+
+/// Entry of a cache bounded by least recent use, which remembers its position
+/// in the cache's access log.
+trait LruEntry: Storable + Clone {
+    fn last_access(&self) -> u64;
+    fn set_last_access(&mut self, last_access: u64);
+}
+
+// Cache bounded by least recent use: entries keyed by id, and an access log of
+// the same ids keyed by tick, least recently used first
+struct Lru<V: LruEntry + 'static> {
+    entries: &'static LocalKey<RefCell<StableBTreeMap<String, V, Memory>>>,
+    log: &'static LocalKey<RefCell<StableBTreeMap<u64, String, Memory>>>,
+    counters: &'static LocalKey<CacheCounters>,
+}
+
+impl<V: LruEntry> Lru<V> {
+    fn next_access(&self) -> u64 {
+        self.log.with(|log| {
+            log.borrow()
+                .last_key_value()
+                .map_or(0, |(tick, _)| tick + 1)
+        })
+    }
+
+    // Marks the entry as most recently used
+    fn get(&self, id: &str) -> Option<V> {
+        let id = id.to_string();
+        let cached = self.entries.with(|entries| entries.borrow().get(&id));
+        self.counters
+            .with(|counters| counters.record(cached.is_some()));
+
+        let mut cached = cached?;
+        let last_access = self.next_access();
+        self.log.with(|log| {
+            let mut log = log.borrow_mut();
+            log.remove(&cached.last_access());
+            log.insert(last_access, id.clone());
+        });
+        cached.set_last_access(last_access);
+        self.entries
+            .with(|entries| entries.borrow_mut().insert(id, cached.clone()));
+        Some(cached)
+    }
+
+    // Evicts the least recently used entries to make room for a new one
+    fn insert(&self, id: &str, mut value: V, capacity: u64) {
+        if capacity == 0 {
+            return;
+        }
+
+        let id = id.to_string();
+        if let Some(previous) = self.entries.with(|entries| entries.borrow().get(&id)) {
+            self.log
+                .with(|log| log.borrow_mut().remove(&previous.last_access()));
+        } else {
+            self.evict(capacity - 1);
+        }
+
+        let last_access = self.next_access();
+        self.log
+            .with(|log| log.borrow_mut().insert(last_access, id.clone()));
+        value.set_last_access(last_access);
+        self.entries
+            .with(|entries| entries.borrow_mut().insert(id, value));
+    }
+
+    fn evict(&self, capacity: u64) {
+        self.log.with(|log| {
+            let mut log = log.borrow_mut();
+            while log.len() > capacity {
+                let Some((_, id)) = log.pop_first() else {
+                    break;
+                };
+                self.entries
+                    .with(|entries| entries.borrow_mut().remove(&id));
+            }
+        });
+    }
+
+    fn stats(&self, capacity: u64) -> CacheStats {
+        let entries = self.entries.with(|entries| entries.borrow().len());
+        self.counters
+            .with(|counters| counters.stats(entries, Some(capacity)))
+    }
+
+    // Returns the number of removed entries
+    fn flush(&self) -> u64 {
+        let removed = self.entries.with(|entries| {
+            let mut entries = entries.borrow_mut();
+            let removed = entries.len();
+            entries.clear_new();
+            removed
+        });
+        self.log.with(|log| log.borrow_mut().clear_new());
+        removed
+    }
+}
+
+fn inscription_info_lru() -> Lru<CachedInscriptionInfo> {
+    Lru {
+        entries: &INSCRIPTION_INFO_CACHE,
+        log: &INSCRIPTION_INFO_LRU,
+        counters: &INSCRIPTION_INFO_COUNTERS,
+    }
+}
+
+fn rune_metadata_lru() -> Lru<CachedRuneMetadata> {
+    Lru {
+        entries: &RUNE_METADATA_CACHE,
+        log: &RUNE_METADATA_LRU,
+        counters: &RUNE_METADATA_COUNTERS,
+    }
+}
+
+struct CacheCounters {
+    hits: Cell<u64>,
+    misses: Cell<u64>,
+}
+
+impl CacheCounters {
+    const fn new() -> Self {
+        Self {
+            hits: Cell::new(0),
+            misses: Cell::new(0),
+        }
+    }
+
+    fn record(&self, hit: bool) {
+        let counter = if hit { &self.hits } else { &self.misses };
+        counter.set(counter.get() + 1);
+    }
+
+    fn stats(&self, entries: u64, capacity: Option<u64>) -> CacheStats {
+        let hits = self.hits.get();
+        let misses = self.misses.get();
+        CacheStats {
+            entries,
+            capacity,
+            hits,
+            misses,
+            hit_ratio: if hits + misses == 0 {
+                0.0
+            } else {
+                hits as f64 / (hits + misses) as f64
+            },
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+pub struct CacheStats {
+    pub entries: u64,
+    // None if the cache is unbounded
+    pub capacity: Option<u64>,
+    // Hits and misses since the last upgrade
+    pub hits: u64,
+    pub misses: u64,
+    pub hit_ratio: f64,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+pub struct CachesStats {
+    pub collection_stats: CacheStats,
+    pub inscription_info: CacheStats,
+    pub rune_market_data: CacheStats,
+    pub rune_metadata: CacheStats,
+}
+
+/// Returns the cached stats of `symbol`, including expired entries. Use
+/// `is_fresh` to check them against the TTL.
+pub fn get_collection_stats(symbol: &str) -> Option<CachedCollectionStats> {
+    COLLECTION_STATS_CACHE.with(|cache| cache.borrow().get(&symbol.to_string()))
+}
+
+pub fn insert_collection_stats(symbol: &str, stats: MaestroCollectionStats, block_height: i64) {
+    COLLECTION_STATS_CACHE.with(|cache| {
+        cache.borrow_mut().insert(
+            symbol.to_string(),
+            CachedCollectionStats {
+                stats,
+                block_height,
+                fetched_at: ic_cdk::api::time(),
+            },
+        );
+    });
+}
+
+/// Returns true if an entry fetched at `fetched_at` is younger than `ttl_secs`.
+pub fn is_fresh(fetched_at: u64, ttl_secs: u64) -> bool {
+    ic_cdk::api::time().saturating_sub(fetched_at) < ttl_secs.saturating_mul(NANOS_PER_SEC)
+}
+
+/// Counts a lookup of the collection stats cache. Expired entries count as misses.
+pub fn record_collection_stats_lookup(hit: bool) {
+    COLLECTION_STATS_COUNTERS.with(|counters| counters.record(hit));
+}
+
+/// Returns the cached market data of `rune_id`, including expired entries.
+/// Use `is_fresh` to check them against the TTL.
+pub fn get_rune_market_data(rune_id: &str) -> Option<CachedRuneMarketData> {
+    RUNE_MARKET_DATA_CACHE.with(|cache| cache.borrow().get(&rune_id.to_string()))
+}
+
+pub fn insert_rune_market_data(
+    rune_id: &str,
+    market_data: MaestroRuneMarketData,
+    block_height: i64,
+) -> CachedRuneMarketData {
+    let cached = CachedRuneMarketData {
+        market_data,
+        block_height,
+        fetched_at: ic_cdk::api::time(),
+    };
+    RUNE_MARKET_DATA_CACHE.with(|cache| {
+        cache
+            .borrow_mut()
+            .insert(rune_id.to_string(), cached.clone())
+    });
+    cached
+}
+
+/// Counts a lookup of the rune market data cache. Expired entries count as misses.
+pub fn record_rune_market_data_lookup(hit: bool) {
+    RUNE_MARKET_DATA_COUNTERS.with(|counters| counters.record(hit));
+}
+
+/// Returns the cached info of `inscription_id` and marks it as most recently used.
+pub fn get_inscription_info(inscription_id: &str) -> Option<CachedInscriptionInfo> {
+    inscription_info_lru().get(inscription_id)
+}
+
+/// Caches the info of `inscription_id`, evicting the least recently used
+/// entries to stay within `inscription_cache_capacity`.
+pub fn insert_inscription_info(inscription_id: &str, collection_symbol: Option<String>) {
+    inscription_info_lru().insert(
+        inscription_id,
+        CachedInscriptionInfo {
+            collection_symbol,
+            last_access: 0,
+        },
+        config().inscription_cache_capacity,
+    );
+}
+
+/// Evicts the least recently used inscriptions until at most `capacity` remain.
+pub fn evict_inscription_info(capacity: u64) {
+    inscription_info_lru().evict(capacity);
+}
+
+/// Returns the cached metadata of `rune_id` and marks it as most recently used.
+pub fn get_rune_metadata(rune_id: &str) -> Option<CachedRuneMetadata> {
+    rune_metadata_lru().get(rune_id)
+}
+
+/// Caches the metadata of `rune_id`, evicting the least recently used entries
+/// to stay within `rune_metadata_cache_capacity`.
+pub fn insert_rune_metadata(
+    rune_id: &str,
+    spaced_name: String,
+    symbol: Option<String>,
+    divisibility: u8,
+) {
+    rune_metadata_lru().insert(
+        rune_id,
+        CachedRuneMetadata {
+            spaced_name,
+            symbol,
+            divisibility,
+            last_access: 0,
+        },
+        config().rune_metadata_cache_capacity,
+    );
+}
+
+/// Evicts the least recently used runes until at most `capacity` remain.
+pub fn evict_rune_metadata(capacity: u64) {
+    rune_metadata_lru().evict(capacity);
+}
+
+#[query(guard = "crate::admin_guard")]
+#[candid_method(query)]
+fn list_collection_stats_cache() -> Vec<CollectionStatsCacheEntry> {
+    COLLECTION_STATS_CACHE.with(|cache| {
+        cache
+            .borrow()
+            .iter()
+            .map(|(collection_symbol, cached)| CollectionStatsCacheEntry {
+                collection_symbol,
+                cached,
+            })
+            .collect()
+    })
+}
+
+// Removes the entry of `collection_symbol`, or every entry if none is given.
+// Returns the number of removed entries.
+#[update(guard = "crate::admin_guard")]
+#[candid_method(update)]
+fn flush_collection_stats_cache(collection_symbol: Option<String>) -> u64 {
+    COLLECTION_STATS_CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        match collection_symbol {
+            Some(symbol) => cache.remove(&symbol).map_or(0, |_| 1),
+            None => {
+                let removed = cache.len();
+                cache.clear_new();
+                removed
+            }
+        }
+    })
+}
+
+#[query(guard = "crate::operator_guard")]
+#[candid_method(query)]
+fn get_cache_stats() -> CachesStats {
+    let config = config();
+    let collection_stats_entries = COLLECTION_STATS_CACHE.with(|cache| cache.borrow().len());
+    let rune_market_data_entries = RUNE_MARKET_DATA_CACHE.with(|cache| cache.borrow().len());
+    CachesStats {
+        collection_stats: COLLECTION_STATS_COUNTERS
+            .with(|counters| counters.stats(collection_stats_entries, None)),
+        inscription_info: inscription_info_lru().stats(config.inscription_cache_capacity),
+        rune_market_data: RUNE_MARKET_DATA_COUNTERS
+            .with(|counters| counters.stats(rune_market_data_entries, None)),
+        rune_metadata: rune_metadata_lru().stats(config.rune_metadata_cache_capacity),
+    }
+}
+
+// Removes every cached inscription info. Returns the number of removed entries.
+#[update(guard = "crate::admin_guard")]
+#[candid_method(update)]
+fn flush_inscription_info_cache() -> u64 {
+    inscription_info_lru().flush()
+}
+
+// Removes every cached rune metadata. Returns the number of removed entries.
+#[update(guard = "crate::admin_guard")]
+#[candid_method(update)]
+fn flush_rune_metadata_cache() -> u64 {
+    rune_metadata_lru().flush()
+}
+
+// Removes the entry of `rune_id`, or every entry if none is given. Returns the
+// number of removed entries.
+#[update(guard = "crate::admin_guard")]
+#[candid_method(update)]
+fn flush_rune_market_data_cache(rune_id: Option<String>) -> u64 {
+    RUNE_MARKET_DATA_CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        match rune_id {
+            Some(rune_id) => cache.remove(&rune_id).map_or(0, |_| 1),
+            None => {
+                let removed = cache.len();
+                cache.clear_new();
+                removed
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert(id: &str, capacity: u64) {
+        inscription_info_lru().insert(
+            id,
+            CachedInscriptionInfo {
+                collection_symbol: None,
+                last_access: 0,
+            },
+            capacity,
+        );
+    }
+
+    // Cached inscription ids, least recently used first, checked against the
+    // entries they point to
+    fn cached_ids() -> Vec<String> {
+        let log: Vec<(u64, String)> =
+            INSCRIPTION_INFO_LRU.with(|log| log.borrow().iter().collect());
+        let entries = INSCRIPTION_INFO_CACHE.with(|cache| cache.borrow().len());
+        assert_eq!(log.len() as u64, entries);
+        for (tick, id) in &log {
+            let entry = INSCRIPTION_INFO_CACHE
+                .with(|cache| cache.borrow().get(id))
+                .unwrap();
+            assert_eq!(entry.last_access, *tick);
+        }
+        log.into_iter().map(|(_, id)| id).collect()
+    }
+
+    #[test]
+    fn hit_moves_entry_to_most_recent() {
+        for id in ["a", "b", "c"] {
+            insert(id, 3);
+        }
+        assert!(get_inscription_info("a").is_some());
+        assert_eq!(cached_ids(), ["b", "c", "a"]);
+
+        insert("d", 3);
+        assert_eq!(cached_ids(), ["c", "a", "d"]);
+    }
+
+    #[test]
+    fn miss_leaves_entries_unchanged() {
+        insert("a", 3);
+        assert!(get_inscription_info("b").is_none());
+        assert_eq!(cached_ids(), ["a"]);
+    }
+
+    #[test]
+    fn insert_at_capacity_evicts_least_recently_used() {
+        for id in ["a", "b", "c", "d"] {
+            insert(id, 3);
+        }
+        assert_eq!(cached_ids(), ["b", "c", "d"]);
+        assert!(get_inscription_info("a").is_none());
+    }
+
+    #[test]
+    fn reinsert_leaves_no_stale_tick() {
+        for id in ["a", "b", "c"] {
+            insert(id, 3);
+        }
+        insert("a", 3);
+        assert_eq!(cached_ids(), ["b", "c", "a"]);
+
+        // At capacity, re-inserting evicts nothing
+        insert("c", 3);
+        assert_eq!(cached_ids(), ["b", "a", "c"]);
+    }
+
+    #[test]
+    fn zero_capacity_caches_nothing() {
+        insert("a", 0);
+        assert!(cached_ids().is_empty());
+    }
+
+    #[test]
+    fn shrinking_capacity_evicts_down_to_new_size() {
+        for id in ["a", "b", "c", "d", "e"] {
+            insert(id, 5);
+        }
+        assert!(get_inscription_info("b").is_some());
+
+        evict_inscription_info(2);
+        assert_eq!(cached_ids(), ["e", "b"]);
+
+        evict_inscription_info(0);
+        assert!(cached_ids().is_empty());
+    }
+}
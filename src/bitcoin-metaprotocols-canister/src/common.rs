@@ -1,27 +1,64 @@
 use candid::CandidType;
-use ic_cdk::api::msg_caller;
 use serde::{Deserialize, Serialize};
 
+use crate::auth::Role;
+
 // Constants
-pub const AUTHORIZED_CALLERS: [&str; 7] = [
-    "62ick-jmsqq-h6wq5-emdfw-qblno-qphae-hs7y3-dxoyp-xiccq-bw4q3-aae", // maestro
-    "xktoe-jjqeb-tzsr3-hxjir-en65h-6agv7-bbq2g-dyoch-276wj-waea7-rqe",
-    "roqha-4aaaa-aaaap-qplnq-cai", // liquidium
-    "e453p-eqaaa-aaaar-qanya-cai",
-    "vr4ua-siaaa-aaaar-qaosq-cai",
-    "pimqm-2dtug-w3ejt-krqai-jlp3u-uux2y-erjcw-wbvhu-pmvhu-hunju-wqe",
-    "daoh3-exchb-6dvbd-fyxld-7kxjo-fdddf-4vhqp-mcoo2-s7gqh-qwpfd-pae",
-];
+// Upper bound on the outpoints of one batch call
+pub const MAX_OUTPOINTS: usize = 100;
 
-pub const BASE_URL: &str = "https://xbt-mainnet.gomaestro-api.org/v0";
+// Initial role assignments, seeded into stable memory at install time. Admins
+// are the canister controllers, plus whoever they grant the role to.
+pub const INITIAL_ROLES: [(&str, Role); 7] = [
+    (
+        "62ick-jmsqq-h6wq5-emdfw-qblno-qphae-hs7y3-dxoyp-xiccq-bw4q3-aae",
+        Role::Reader,
+    ), // maestro
+    (
+        "xktoe-jjqeb-tzsr3-hxjir-en65h-6agv7-bbq2g-dyoch-276wj-waea7-rqe",
+        Role::Reader,
+    ),
+    ("roqha-4aaaa-aaaap-qplnq-cai", Role::Reader), // liquidium
+    ("e453p-eqaaa-aaaar-qanya-cai", Role::Reader),
+    ("vr4ua-siaaa-aaaar-qaosq-cai", Role::Reader),
+    (
+        "pimqm-2dtug-w3ejt-krqai-jlp3u-uux2y-erjcw-wbvhu-pmvhu-hunju-wqe",
+        Role::Reader,
+    ),
+    (
+        "daoh3-exchb-6dvbd-fyxld-7kxjo-fdddf-4vhqp-mcoo2-s7gqh-qwpfd-pae",
+        Role::Reader,
+    ),
+];
 
 // Common types
+/// Rarity of a sat, as defined by the ordinals theory.
+#[derive(
+    CandidType, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Rarity {
+    Common,
+    Uncommon,
+    Rare,
+    Epic,
+    Legendary,
+    Mythic,
+}
+
 #[derive(CandidType, Deserialize, Serialize, Debug)]
 pub struct LastUpdated {
     pub block_hash: String,
     pub block_height: i64,
 }
 
+/// Transaction output, identified by its transaction id and output index.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct Outpoint {
+    pub txid: String,
+    pub vout: u32,
+}
+
 #[derive(CandidType, Deserialize, Serialize, Debug)]
 pub struct MaestroInscriptionInfo {
     pub collection_symbol: Option<String>,
@@ -34,7 +71,7 @@ pub struct MaestroInscriptionInfoResponse {
     pub next_cursor: Option<String>,
 }
 
-#[derive(CandidType, Deserialize, Serialize, Debug)]
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
 pub struct MaestroCollectionStats {
     pub floor_price: Option<String>,
 }
@@ -59,13 +96,31 @@ pub struct MaestroOmbColorGroup {
     pub data: MaestroOmbColorGroupData,
 }
 
-// Utility functions
-pub fn check_authorization() -> Result<(), String> {
-    let caller = msg_caller();
-    let caller_str = caller.to_text();
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+pub struct MaestroRuneInfo {
+    pub id: String,
+    pub spaced_name: String,
+    pub symbol: Option<String>,
+    pub divisibility: u8,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+pub struct MaestroRuneInfoResponse {
+    pub data: MaestroRuneInfo,
+    pub last_updated: LastUpdated,
+}
 
-    if !AUTHORIZED_CALLERS.iter().any(|&auth| auth == caller_str) {
-        return Err("Unauthorized".into());
-    }
-    Ok(())
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct MaestroRuneMarketData {
+    // Floor price of one whole rune, in sats
+    pub floor_unit_price: Option<String>,
+    // Traded volume over the last 24 hours, in sats
+    pub volume_24h: Option<String>,
+    pub holders: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+pub struct MaestroRuneMarketDataResponse {
+    pub data: MaestroRuneMarketData,
+    pub last_updated: LastUpdated,
 }
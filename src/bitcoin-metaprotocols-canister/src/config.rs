@@ -0,0 +1,520 @@
+use candid::{candid_method, CandidType};
+use ic_cdk_macros::*;
+use ic_stable_structures::{StableCell, Storable};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use crate::address::{AddressParams, MAINNET_PARAMS, REGTEST_PARAMS, TESTNET_PARAMS};
+use crate::auth::{Role, RoleAssignment};
+use crate::cache;
+use crate::error::CanisterError;
+use crate::memory::{get_memory, Memory, CONFIG_MEMORY_ID};
+
+// Upper bound on `max_concurrent_outcalls`, well below the subnet's limit on in-flight outcalls
+const MAX_CONCURRENT_OUTCALLS_LIMIT: u32 = 50;
+
+// Bounds of `max_outcalls_per_call`. The lower bound leaves room for one page
+// and the enrichment of a few of its inscriptions.
+const MIN_OUTCALLS_PER_CALL: u32 = 10;
+const MAX_OUTCALLS_PER_CALL_LIMIT: u32 = 5000;
+
+/// Bitcoin network the canister serves.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Network {
+    #[default]
+    Mainnet,
+    Testnet,
+    Testnet4,
+    Signet,
+    /// Maestro does not host regtest, a `base_url` must be given
+    Regtest,
+}
+
+impl Network {
+    /// Maestro endpoint of the network, if Maestro hosts it
+    pub fn default_base_url(&self) -> Option<&'static str> {
+        match self {
+            Self::Mainnet => Some("https://xbt-mainnet.gomaestro-api.org/v0"),
+            Self::Testnet => Some("https://xbt-testnet.gomaestro-api.org/v0"),
+            Self::Testnet4 => Some("https://xbt-testnet4.gomaestro-api.org/v0"),
+            Self::Signet => Some("https://xbt-signet.gomaestro-api.org/v0"),
+            Self::Regtest => None,
+        }
+    }
+
+    pub fn address_params(&self) -> &'static AddressParams {
+        match self {
+            Self::Mainnet => &MAINNET_PARAMS,
+            Self::Testnet | Self::Testnet4 | Self::Signet => &TESTNET_PARAMS,
+            Self::Regtest => &REGTEST_PARAMS,
+        }
+    }
+}
+
+/// Runtime configuration, set by the install and upgrade arguments. Everything
+/// but the network is also tunable by operators.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct Config {
+    #[serde(default)]
+    pub network: Network,
+    /// Overrides the Maestro endpoint of the network
+    #[serde(default)]
+    pub base_url: Option<String>,
+    /// Maximum number of Maestro outcalls in flight at once within one call.
+    /// Batch lookups and enrichment run their outcalls concurrently up to this
+    /// bound, and still return results in the order of their input.
+    #[serde(default = "default_max_concurrent_outcalls")]
+    pub max_concurrent_outcalls: u32,
+    /// How long cached collection stats are served before being refetched, 0 disables the cache
+    #[serde(default = "default_collection_stats_ttl_secs")]
+    pub collection_stats_ttl_secs: u64,
+    /// Maximum number of inscriptions kept in the inscription info cache, 0 disables the cache
+    #[serde(default = "default_inscription_cache_capacity")]
+    pub inscription_cache_capacity: u64,
+    /// Maximum number of outcalls made by one call of `get_all_address_inscriptions`
+    /// or `get_address_runes`, counting both the listing and the enrichment
+    #[serde(default = "default_max_outcalls_per_call")]
+    pub max_outcalls_per_call: u32,
+    /// Whether address inscriptions are completed with their collection's floor price
+    #[serde(default = "default_true")]
+    pub fetch_floor_prices: bool,
+    /// Whether inscriptions are completed with their OMB color group
+    #[serde(default = "default_true")]
+    pub fetch_omb_color_groups: bool,
+    /// Whether rune balances are completed with the rune's market data
+    #[serde(default = "default_true")]
+    pub fetch_rune_market_data: bool,
+    /// How long cached rune market data is served before being refetched, 0 disables the cache
+    #[serde(default = "default_rune_market_data_ttl_secs")]
+    pub rune_market_data_ttl_secs: u64,
+    /// Maximum number of runes kept in the rune metadata cache, 0 disables the cache
+    #[serde(default = "default_rune_metadata_cache_capacity")]
+    pub rune_metadata_cache_capacity: u64,
+}
+
+fn default_max_concurrent_outcalls() -> u32 {
+    10
+}
+
+fn default_collection_stats_ttl_secs() -> u64 {
+    10 * 60
+}
+
+fn default_inscription_cache_capacity() -> u64 {
+    100_000
+}
+
+fn default_max_outcalls_per_call() -> u32 {
+    1000
+}
+
+fn default_rune_market_data_ttl_secs() -> u64 {
+    10 * 60
+}
+
+fn default_rune_metadata_cache_capacity() -> u64 {
+    100_000
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            network: Network::default(),
+            base_url: None,
+            max_concurrent_outcalls: default_max_concurrent_outcalls(),
+            collection_stats_ttl_secs: default_collection_stats_ttl_secs(),
+            inscription_cache_capacity: default_inscription_cache_capacity(),
+            max_outcalls_per_call: default_max_outcalls_per_call(),
+            fetch_floor_prices: default_true(),
+            fetch_omb_color_groups: default_true(),
+            fetch_rune_market_data: default_true(),
+            rune_market_data_ttl_secs: default_rune_market_data_ttl_secs(),
+            rune_metadata_cache_capacity: default_rune_metadata_cache_capacity(),
+        }
+    }
+}
+
+impl Storable for Config {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(serde_json::to_vec(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    const BOUND: ic_stable_structures::storable::Bound =
+        ic_stable_structures::storable::Bound::Unbounded;
+}
+
+impl Config {
+    /// Maestro endpoint the canister sends its requests to
+    pub fn maestro_base_url(&self) -> String {
+        self.base_url
+            .clone()
+            .or_else(|| self.network.default_base_url().map(str::to_string))
+            .unwrap_or_default()
+    }
+}
+
+/// Argument of the canister, on install and on upgrade.
+#[derive(CandidType, Deserialize, Debug)]
+pub enum CanisterArg {
+    Init(InitArgs),
+    Upgrade(UpgradeArgs),
+}
+
+#[derive(CandidType, Deserialize, Debug, Default)]
+pub struct InitArgs {
+    pub network: Network,
+    // Required for regtest
+    pub base_url: Option<String>,
+    // Replaces `INITIAL_ROLES` if given
+    pub roles: Option<Vec<RoleAssignment>>,
+    // Applied on top of the defaults
+    pub config: Option<ConfigUpdate>,
+}
+
+/// Fields left empty keep their current value.
+#[derive(CandidType, Deserialize, Debug, Default)]
+pub struct UpgradeArgs {
+    pub network: Option<Network>,
+    pub base_url: Option<String>,
+    // Granted on top of the current role assignments
+    pub roles: Option<Vec<RoleAssignment>>,
+    pub config: Option<ConfigUpdate>,
+}
+
+/// Partial update of the configuration. Fields left empty are unchanged.
+#[derive(CandidType, Deserialize, Serialize, Debug, Default)]
+pub struct ConfigUpdate {
+    pub max_concurrent_outcalls: Option<u32>,
+    pub collection_stats_ttl_secs: Option<u64>,
+    pub inscription_cache_capacity: Option<u64>,
+    pub max_outcalls_per_call: Option<u32>,
+    pub fetch_floor_prices: Option<bool>,
+    pub fetch_omb_color_groups: Option<bool>,
+    pub fetch_rune_market_data: Option<bool>,
+    pub rune_market_data_ttl_secs: Option<u64>,
+    pub rune_metadata_cache_capacity: Option<u64>,
+}
+
+thread_local! {
+    static CONFIG_STORAGE: RefCell<StableCell<Config, Memory>> = RefCell::new(
+        StableCell::init(get_memory(CONFIG_MEMORY_ID), Config::default()).unwrap()
+    );
+}
+
+pub fn config() -> Config {
+    CONFIG_STORAGE.with(|storage| storage.borrow().get().clone())
+}
+
+fn save_config(config: Config) {
+    CONFIG_STORAGE.with(|storage| {
+        storage
+            .borrow_mut()
+            .set(config)
+            .map(|_| ())
+            .unwrap_or_else(|e| ic_cdk::trap(format!("Failed to save config: {:?}", e)));
+    });
+}
+
+fn set_network(
+    config: &mut Config,
+    network: Network,
+    base_url: Option<String>,
+) -> Result<(), CanisterError> {
+    if let Some(ref base_url) = base_url {
+        if !base_url.starts_with("https://") {
+            return Err(CanisterError::InvalidInput(
+                "base_url must be an https URL".to_string(),
+            ));
+        }
+    }
+    if base_url.is_none() && network.default_base_url().is_none() {
+        return Err(CanisterError::InvalidInput(format!(
+            "base_url is required on {:?}",
+            network
+        )));
+    }
+
+    config.network = network;
+    config.base_url = base_url.map(|base_url| base_url.trim_end_matches('/').to_string());
+    Ok(())
+}
+
+/// Applies the network and configuration of the install argument.
+pub fn init(args: InitArgs) -> Result<(), CanisterError> {
+    let mut config = config();
+    set_network(&mut config, args.network, args.base_url)?;
+    if let Some(update) = args.config {
+        apply_update(&mut config, update)?;
+    }
+    save_config(config);
+    Ok(())
+}
+
+/// Applies the network and configuration of the upgrade argument on top of
+/// the stored configuration.
+pub fn upgrade(args: UpgradeArgs) -> Result<(), CanisterError> {
+    let mut config = config();
+    apply_upgrade(&mut config, args)?;
+
+    let inscription_cache_capacity = config.inscription_cache_capacity;
+    let rune_metadata_cache_capacity = config.rune_metadata_cache_capacity;
+    save_config(config);
+    cache::evict_inscription_info(inscription_cache_capacity);
+    cache::evict_rune_metadata(rune_metadata_cache_capacity);
+    Ok(())
+}
+
+fn apply_upgrade(config: &mut Config, args: UpgradeArgs) -> Result<(), CanisterError> {
+    // A new network drops the previous network's base URL unless one is given
+    let base_url = match args.network {
+        Some(network) if network != config.network => args.base_url,
+        _ => args.base_url.or(config.base_url.clone()),
+    };
+    let network = args.network.unwrap_or(config.network);
+    set_network(config, network, base_url)?;
+    if let Some(update) = args.config {
+        apply_update(config, update)?;
+    }
+    Ok(())
+}
+
+#[query(guard = "crate::reader_guard")]
+#[candid_method(query)]
+fn get_config() -> Config {
+    config()
+}
+
+#[update]
+#[candid_method(update)]
+fn update_config(update: ConfigUpdate) -> Result<(), CanisterError> {
+    crate::authorize(Role::Operator)?;
+
+    let mut config = config();
+    apply_update(&mut config, update)?;
+
+    let inscription_cache_capacity = config.inscription_cache_capacity;
+    let rune_metadata_cache_capacity = config.rune_metadata_cache_capacity;
+    save_config(config);
+    cache::evict_inscription_info(inscription_cache_capacity);
+    cache::evict_rune_metadata(rune_metadata_cache_capacity);
+    Ok(())
+}
+
+fn apply_update(config: &mut Config, update: ConfigUpdate) -> Result<(), CanisterError> {
+    if let Some(max_concurrent_outcalls) = update.max_concurrent_outcalls {
+        if !(1..=MAX_CONCURRENT_OUTCALLS_LIMIT).contains(&max_concurrent_outcalls) {
+            return Err(CanisterError::InvalidInput(format!(
+                "max_concurrent_outcalls must be between 1 and {}",
+                MAX_CONCURRENT_OUTCALLS_LIMIT
+            )));
+        }
+        config.max_concurrent_outcalls = max_concurrent_outcalls;
+    }
+
+    if let Some(collection_stats_ttl_secs) = update.collection_stats_ttl_secs {
+        config.collection_stats_ttl_secs = collection_stats_ttl_secs;
+    }
+
+    if let Some(inscription_cache_capacity) = update.inscription_cache_capacity {
+        config.inscription_cache_capacity = inscription_cache_capacity;
+    }
+
+    if let Some(max_outcalls_per_call) = update.max_outcalls_per_call {
+        if !(MIN_OUTCALLS_PER_CALL..=MAX_OUTCALLS_PER_CALL_LIMIT).contains(&max_outcalls_per_call) {
+            return Err(CanisterError::InvalidInput(format!(
+                "max_outcalls_per_call must be between {} and {}",
+                MIN_OUTCALLS_PER_CALL, MAX_OUTCALLS_PER_CALL_LIMIT
+            )));
+        }
+        config.max_outcalls_per_call = max_outcalls_per_call;
+    }
+
+    if let Some(fetch_floor_prices) = update.fetch_floor_prices {
+        config.fetch_floor_prices = fetch_floor_prices;
+    }
+
+    if let Some(fetch_omb_color_groups) = update.fetch_omb_color_groups {
+        config.fetch_omb_color_groups = fetch_omb_color_groups;
+    }
+
+    if let Some(fetch_rune_market_data) = update.fetch_rune_market_data {
+        config.fetch_rune_market_data = fetch_rune_market_data;
+    }
+
+    if let Some(rune_market_data_ttl_secs) = update.rune_market_data_ttl_secs {
+        config.rune_market_data_ttl_secs = rune_market_data_ttl_secs;
+    }
+
+    if let Some(rune_metadata_cache_capacity) = update.rune_metadata_cache_capacity {
+        config.rune_metadata_cache_capacity = rune_metadata_cache_capacity;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CUSTOM_URL: &str = "https://maestro.example.com/v0";
+
+    fn custom_mainnet() -> Config {
+        Config {
+            base_url: Some(CUSTOM_URL.to_string()),
+            ..Config::default()
+        }
+    }
+
+    fn invalid_input<T: std::fmt::Debug>(result: Result<T, CanisterError>) -> bool {
+        matches!(result, Err(CanisterError::InvalidInput(_)))
+    }
+
+    #[test]
+    fn upgrade_without_network_keeps_base_url() {
+        let mut config = custom_mainnet();
+        apply_upgrade(&mut config, UpgradeArgs::default()).unwrap();
+        assert_eq!(config.network, Network::Mainnet);
+        assert_eq!(config.base_url.as_deref(), Some(CUSTOM_URL));
+    }
+
+    #[test]
+    fn upgrade_to_same_network_keeps_base_url() {
+        let mut config = custom_mainnet();
+        let args = UpgradeArgs {
+            network: Some(Network::Mainnet),
+            ..UpgradeArgs::default()
+        };
+        apply_upgrade(&mut config, args).unwrap();
+        assert_eq!(config.base_url.as_deref(), Some(CUSTOM_URL));
+    }
+
+    #[test]
+    fn upgrade_to_new_network_drops_base_url() {
+        let mut config = custom_mainnet();
+        let args = UpgradeArgs {
+            network: Some(Network::Testnet4),
+            ..UpgradeArgs::default()
+        };
+        apply_upgrade(&mut config, args).unwrap();
+        assert_eq!(config.network, Network::Testnet4);
+        assert_eq!(config.base_url, None);
+        assert_eq!(
+            config.maestro_base_url(),
+            "https://xbt-testnet4.gomaestro-api.org/v0"
+        );
+    }
+
+    #[test]
+    fn upgrade_to_new_network_takes_given_base_url() {
+        let mut config = Config::default();
+        let args = UpgradeArgs {
+            network: Some(Network::Signet),
+            base_url: Some(format!("{}/", CUSTOM_URL)),
+            ..UpgradeArgs::default()
+        };
+        apply_upgrade(&mut config, args).unwrap();
+        assert_eq!(config.network, Network::Signet);
+        assert_eq!(config.base_url.as_deref(), Some(CUSTOM_URL));
+    }
+
+    #[test]
+    fn regtest_requires_base_url() {
+        let mut config = custom_mainnet();
+        let args = UpgradeArgs {
+            network: Some(Network::Regtest),
+            ..UpgradeArgs::default()
+        };
+        assert!(invalid_input(apply_upgrade(&mut config, args)));
+
+        let mut config = Config::default();
+        assert!(invalid_input(set_network(
+            &mut config,
+            Network::Regtest,
+            None
+        )));
+        set_network(&mut config, Network::Regtest, Some(CUSTOM_URL.to_string())).unwrap();
+        assert_eq!(config.maestro_base_url(), CUSTOM_URL);
+    }
+
+    #[test]
+    fn base_url_must_be_https() {
+        let mut config = Config::default();
+        assert!(invalid_input(set_network(
+            &mut config,
+            Network::Mainnet,
+            Some("http://maestro.example.com".to_string())
+        )));
+    }
+
+    #[test]
+    fn update_changes_only_given_fields() {
+        let mut config = custom_mainnet();
+        let update = ConfigUpdate {
+            max_concurrent_outcalls: Some(20),
+            fetch_floor_prices: Some(false),
+            ..ConfigUpdate::default()
+        };
+        apply_update(&mut config, update).unwrap();
+
+        let expected = Config {
+            max_concurrent_outcalls: 20,
+            fetch_floor_prices: false,
+            ..custom_mainnet()
+        };
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn update_rejects_out_of_range_values() {
+        for max_concurrent_outcalls in [0, MAX_CONCURRENT_OUTCALLS_LIMIT + 1] {
+            let update = ConfigUpdate {
+                max_concurrent_outcalls: Some(max_concurrent_outcalls),
+                ..ConfigUpdate::default()
+            };
+            assert!(invalid_input(apply_update(&mut Config::default(), update)));
+        }
+        for max_outcalls_per_call in [MIN_OUTCALLS_PER_CALL - 1, MAX_OUTCALLS_PER_CALL_LIMIT + 1] {
+            let update = ConfigUpdate {
+                max_outcalls_per_call: Some(max_outcalls_per_call),
+                ..ConfigUpdate::default()
+            };
+            assert!(invalid_input(apply_update(&mut Config::default(), update)));
+        }
+
+        let update = ConfigUpdate {
+            max_concurrent_outcalls: Some(MAX_CONCURRENT_OUTCALLS_LIMIT),
+            max_outcalls_per_call: Some(MIN_OUTCALLS_PER_CALL),
+            ..ConfigUpdate::default()
+        };
+        let mut config = Config::default();
+        apply_update(&mut config, update).unwrap();
+        assert_eq!(
+            config.max_concurrent_outcalls,
+            MAX_CONCURRENT_OUTCALLS_LIMIT
+        );
+        assert_eq!(config.max_outcalls_per_call, MIN_OUTCALLS_PER_CALL);
+    }
+
+    #[test]
+    fn upgrade_applies_config_update() {
+        let mut config = custom_mainnet();
+        let args = UpgradeArgs {
+            config: Some(ConfigUpdate {
+                inscription_cache_capacity: Some(10),
+                ..ConfigUpdate::default()
+            }),
+            ..UpgradeArgs::default()
+        };
+        apply_upgrade(&mut config, args).unwrap();
+        assert_eq!(config.inscription_cache_capacity, 10);
+        assert_eq!(config.base_url.as_deref(), Some(CUSTOM_URL));
+    }
+}
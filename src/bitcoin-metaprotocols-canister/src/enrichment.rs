@@ -0,0 +1,314 @@
+use candid::CandidType;
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::cache::{self, CachedRuneMarketData};
+use crate::common::{
+    MaestroCollectionStats, MaestroCollectionStatsResponse, MaestroInscriptionInfoResponse,
+    MaestroOmbColorGroup, MaestroRuneInfoResponse, MaestroRuneMarketDataResponse,
+};
+use crate::config::config;
+use crate::maestro_client::MaestroClient;
+
+/// Data fetched from Maestro to complete an inscription.
+pub struct InscriptionEnrichment {
+    pub collection_symbol: Option<String>,
+    // Zero unless a floor price was requested and the inscription belongs to a collection
+    pub floor_price: i64,
+    pub omb_color: Option<String>,
+    pub omb_floor_price: Option<i64>,
+}
+
+/// Rune metadata fixed at etching.
+pub struct RuneMetadata {
+    pub spaced_name: String,
+    pub symbol: Option<String>,
+    pub divisibility: u8,
+}
+
+/// Market data of a rune, as cached.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
+pub struct RuneMarketData {
+    // Floor price of one whole rune, in sats, as the decimal Maestro reports
+    pub floor_unit_price_sats: Option<String>,
+    // Traded volume over the last 24 hours, in sats, as the decimal Maestro reports
+    pub volume_24h_sats: Option<String>,
+    pub holders: Option<u64>,
+    // Block height Maestro reported when the market data was fetched
+    pub block_height: i64,
+    // Nanoseconds since the UNIX epoch
+    pub fetched_at: u64,
+}
+
+impl From<CachedRuneMarketData> for RuneMarketData {
+    fn from(cached: CachedRuneMarketData) -> Self {
+        Self {
+            floor_unit_price_sats: cached.market_data.floor_unit_price,
+            volume_24h_sats: cached.market_data.volume_24h,
+            holders: cached.market_data.holders,
+            block_height: cached.block_height,
+            fetched_at: cached.fetched_at,
+        }
+    }
+}
+
+/// Data fetched from Maestro to complete a rune balance.
+pub struct RuneEnrichment {
+    pub metadata: Option<RuneMetadata>,
+    pub market_data: Option<RuneMarketData>,
+}
+
+/// Fetches the collection and, if enabled, the OMB color group of each
+/// inscription. With `with_floor_price`, also fetches the floor price of each
+/// collection once, unless floor prices are disabled.
+pub async fn enrich_inscriptions(
+    client: &MaestroClient,
+    inscription_ids: Vec<String>,
+    with_floor_price: bool,
+) -> Vec<InscriptionEnrichment> {
+    let config = config();
+    let max_concurrent_outcalls = config.max_concurrent_outcalls as usize;
+    let fetch_omb_color_groups = config.fetch_omb_color_groups;
+
+    // Fetch inscription info and OMB color group
+    let mut enrichments: Vec<InscriptionEnrichment> = stream::iter(inscription_ids)
+        .map(|inscription_id| async move {
+            let collection_symbol = fetch_collection_symbol(client, &inscription_id).await;
+            let (omb_color, omb_floor_price) = if fetch_omb_color_groups {
+                fetch_omb_color_group(client, &inscription_id).await
+            } else {
+                (None, None)
+            };
+            InscriptionEnrichment {
+                collection_symbol,
+                floor_price: 0,
+                omb_color,
+                omb_floor_price,
+            }
+        })
+        .buffered(max_concurrent_outcalls)
+        .collect()
+        .await;
+
+    if !with_floor_price || !config.fetch_floor_prices {
+        return enrichments;
+    }
+
+    // Fetch the floor price once per collection, however many inscriptions share it
+    let symbols: BTreeSet<String> = enrichments
+        .iter()
+        .filter_map(|enrichment| enrichment.collection_symbol.clone())
+        .collect();
+    let floor_prices: BTreeMap<String, i64> = stream::iter(symbols)
+        .map(|symbol| async move {
+            let floor_price =
+                fetch_floor_price(client, &symbol, config.collection_stats_ttl_secs).await;
+            (symbol, floor_price)
+        })
+        .buffered(max_concurrent_outcalls)
+        .collect()
+        .await;
+
+    for enrichment in &mut enrichments {
+        if let Some(ref symbol) = enrichment.collection_symbol {
+            enrichment.floor_price = floor_prices.get(symbol).copied().unwrap_or(0);
+        }
+    }
+    enrichments
+}
+
+// Inscription info is immutable, so cached entries never expire. Failed
+// lookups are not cached.
+async fn fetch_collection_symbol(client: &MaestroClient, inscription_id: &str) -> Option<String> {
+    if let Some(cached) = cache::get_inscription_info(inscription_id) {
+        return cached.collection_symbol;
+    }
+
+    match client
+        .get_json::<MaestroInscriptionInfoResponse>(
+            &format!(
+                "/assets/inscriptions/{}",
+                urlencoding::encode(inscription_id)
+            ),
+            &[],
+        )
+        .await
+    {
+        Ok(info_response) => {
+            let collection_symbol = info_response.data.collection_symbol;
+            cache::insert_inscription_info(inscription_id, collection_symbol.clone());
+            collection_symbol
+        }
+        Err(e) => {
+            ic_cdk::println!(
+                "Failed to fetch inscription info for {}: {}",
+                inscription_id,
+                e
+            );
+            None
+        }
+    }
+}
+
+fn parse_floor_price(stats: &MaestroCollectionStats) -> i64 {
+    stats
+        .floor_price
+        .as_deref()
+        .unwrap_or("0")
+        .parse::<i64>()
+        .unwrap_or(0)
+}
+
+// Serves the floor price from the cache while it is fresh. If Maestro cannot be
+// reached, falls back to the expired cache entry rather than reporting no price.
+async fn fetch_floor_price(client: &MaestroClient, symbol: &str, ttl_secs: u64) -> i64 {
+    let cached = cache::get_collection_stats(symbol);
+    let fresh = cached
+        .as_ref()
+        .is_some_and(|cached| cache::is_fresh(cached.fetched_at, ttl_secs));
+    cache::record_collection_stats_lookup(fresh);
+    if let (true, Some(cached)) = (fresh, &cached) {
+        return parse_floor_price(&cached.stats);
+    }
+
+    match client
+        .get_json::<MaestroCollectionStatsResponse>(
+            &format!("/assets/collections/{}/stats", urlencoding::encode(symbol)),
+            &[],
+        )
+        .await
+    {
+        Ok(stats_response) => {
+            let floor_price = parse_floor_price(&stats_response.data);
+            cache::insert_collection_stats(
+                symbol,
+                stats_response.data,
+                stats_response.last_updated.block_height,
+            );
+            floor_price
+        }
+        Err(e) => {
+            ic_cdk::println!("Failed to fetch collection stats for {}: {}", symbol, e);
+            cached.map_or(0, |cached| parse_floor_price(&cached.stats))
+        }
+    }
+}
+
+// Most inscriptions are not OMBs, so failures are expected and not logged
+async fn fetch_omb_color_group(
+    client: &MaestroClient,
+    inscription_id: &str,
+) -> (Option<String>, Option<i64>) {
+    match client
+        .get_json::<MaestroOmbColorGroup>(
+            &format!(
+                "/assets/inscriptions/{}/omb_color_group",
+                urlencoding::encode(inscription_id)
+            ),
+            &[],
+        )
+        .await
+    {
+        Ok(omb) => (Some(omb.data.omb_color), Some(omb.data.omb_floor_price)),
+        Err(_) => (None, None),
+    }
+}
+
+/// Fetches the metadata and, if enabled, the market data of each rune.
+pub async fn enrich_runes(client: &MaestroClient, rune_ids: Vec<String>) -> Vec<RuneEnrichment> {
+    let config = config();
+
+    stream::iter(rune_ids)
+        .map(|rune_id| async move {
+            let metadata = fetch_rune_metadata(client, &rune_id).await;
+            let market_data = if config.fetch_rune_market_data {
+                fetch_rune_market_data(client, &rune_id, config.rune_market_data_ttl_secs).await
+            } else {
+                None
+            };
+            RuneEnrichment {
+                metadata,
+                market_data,
+            }
+        })
+        .buffered(config.max_concurrent_outcalls as usize)
+        .collect()
+        .await
+}
+
+// Metadata never changes once a rune is etched, so cached entries never expire
+async fn fetch_rune_metadata(client: &MaestroClient, rune_id: &str) -> Option<RuneMetadata> {
+    if let Some(cached) = cache::get_rune_metadata(rune_id) {
+        return Some(RuneMetadata {
+            spaced_name: cached.spaced_name,
+            symbol: cached.symbol,
+            divisibility: cached.divisibility,
+        });
+    }
+
+    match client
+        .get_json::<MaestroRuneInfoResponse>(
+            &format!("/assets/runes/{}", urlencoding::encode(rune_id)),
+            &[],
+        )
+        .await
+    {
+        Ok(info_response) => {
+            let info = info_response.data;
+            cache::insert_rune_metadata(
+                rune_id,
+                info.spaced_name.clone(),
+                info.symbol.clone(),
+                info.divisibility,
+            );
+            Some(RuneMetadata {
+                spaced_name: info.spaced_name,
+                symbol: info.symbol,
+                divisibility: info.divisibility,
+            })
+        }
+        Err(e) => {
+            ic_cdk::println!("Failed to fetch rune info for {}: {}", rune_id, e);
+            None
+        }
+    }
+}
+
+// Serves the market data from the cache while it is fresh. If Maestro cannot
+// be reached, falls back to the expired cache entry.
+async fn fetch_rune_market_data(
+    client: &MaestroClient,
+    rune_id: &str,
+    ttl_secs: u64,
+) -> Option<RuneMarketData> {
+    let cached = cache::get_rune_market_data(rune_id);
+    let fresh = cached
+        .as_ref()
+        .is_some_and(|cached| cache::is_fresh(cached.fetched_at, ttl_secs));
+    cache::record_rune_market_data_lookup(fresh);
+    if fresh {
+        return cached.map(RuneMarketData::from);
+    }
+
+    match client
+        .get_json::<MaestroRuneMarketDataResponse>(
+            &format!("/assets/runes/{}/market", urlencoding::encode(rune_id)),
+            &[],
+        )
+        .await
+    {
+        Ok(market_response) => Some(
+            cache::insert_rune_market_data(
+                rune_id,
+                market_response.data,
+                market_response.last_updated.block_height,
+            )
+            .into(),
+        ),
+        Err(e) => {
+            ic_cdk::println!("Failed to fetch rune market data for {}: {}", rune_id, e);
+            cached.map(RuneMarketData::from)
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use candid::CandidType;
+use ic_cdk::call::Error as CallError;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// Reject code of transient system errors, on which a retry may succeed
+const SYS_TRANSIENT_REJECT_CODE: u32 = 2;
+
+/// Error returned by every endpoint. Callers can match on the variant to
+/// decide whether a retry is worthwhile.
+#[derive(CandidType, Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub enum CanisterError {
+    /// The caller lacks the required role. Guards reject the call with this
+    /// variant's message before the method runs.
+    Unauthorized,
+    /// No usable Maestro API key is configured
+    ApiKeyNotSet,
+    InvalidInput(String),
+    NotFound(String),
+    /// The address is malformed or of an unsupported type
+    InvalidAddress(String),
+    /// The address is valid but belongs to another network than the canister's
+    AddressNetworkMismatch {
+        expected: String,
+        found: String,
+    },
+    /// Another request from the same caller is still being processed
+    RequestInProgress,
+    /// Maestro answered with a non-success HTTP status
+    UpstreamHttp {
+        status: u16,
+    },
+    /// The HTTP outcall was rejected by the system before reaching Maestro
+    UpstreamRejected {
+        code: u32,
+        message: String,
+    },
+    /// The Maestro response could not be deserialized
+    DecodeError(String),
+    /// Maestro rate limited every configured API key
+    RateLimited,
+    /// The canister does not hold enough cycles to pay for the outcall
+    CyclesInsufficient {
+        available: u128,
+        required: u128,
+    },
+}
+
+impl fmt::Display for CanisterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unauthorized => write!(f, "Unauthorized: Caller does not have the required role"),
+            Self::ApiKeyNotSet => write!(f, "API key not set"),
+            Self::InvalidInput(message) => write!(f, "Invalid input: {}", message),
+            Self::NotFound(message) => write!(f, "Not found: {}", message),
+            Self::InvalidAddress(message) => write!(f, "Invalid address: {}", message),
+            Self::AddressNetworkMismatch { expected, found } => write!(
+                f,
+                "Address belongs to {} but the canister is on {}",
+                found, expected
+            ),
+            Self::RequestInProgress => write!(f, "Already processing a request for this caller"),
+            Self::UpstreamHttp { status } => write!(f, "HTTP status {} from Maestro", status),
+            Self::UpstreamRejected { code, message } => {
+                write!(f, "HTTP outcall rejected with code {}: {}", code, message)
+            }
+            Self::DecodeError(message) => write!(f, "Failed to parse: {}", message),
+            Self::RateLimited => write!(f, "Rate limited by Maestro"),
+            Self::CyclesInsufficient {
+                available,
+                required,
+            } => write!(
+                f,
+                "Insufficient cycles: {} available, {} required",
+                available, required
+            ),
+        }
+    }
+}
+
+impl From<CallError> for CanisterError {
+    fn from(error: CallError) -> Self {
+        match error {
+            CallError::InsufficientLiquidCycleBalance(e) => Self::CyclesInsufficient {
+                available: e.available,
+                required: e.required,
+            },
+            CallError::CallPerformFailed(e) => Self::UpstreamRejected {
+                code: SYS_TRANSIENT_REJECT_CODE,
+                message: e.to_string(),
+            },
+            CallError::CallRejected(e) => Self::UpstreamRejected {
+                code: e.raw_reject_code(),
+                message: e.reject_message().to_string(),
+            },
+            CallError::CandidDecodeFailed(e) => Self::DecodeError(e.to_string()),
+        }
+    }
+}
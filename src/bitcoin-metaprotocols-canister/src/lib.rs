@@ -1,51 +1,60 @@
-use candid::{candid_method, CandidType, Principal};
+use candid::Principal;
 use ic_cdk::api::msg_caller;
 use ic_cdk::management_canister::{HttpRequestResult, TransformArgs};
 use ic_cdk_macros::*;
-use ic_stable_structures::{DefaultMemoryImpl, StableCell, Storable};
-use serde::{Deserialize, Serialize};
-use std::borrow::Cow;
 use std::cell::RefCell;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 
+mod address;
+mod address_brc20;
 mod address_inscriptions;
+mod address_runes;
+mod api_keys;
+mod auth;
+mod cache;
 mod common;
+mod config;
+mod enrichment;
+mod error;
+mod maestro_client;
+mod memory;
+mod migrations;
+mod sat;
+mod sat_ranges;
+mod spendability;
 mod utxo_inscriptions;
+mod validation;
 
 // Re-export public functions and types to maintain the same API
-pub use address_inscriptions::{get_address_inscriptions, AddressInscription, AddressInscriptions};
-pub use common::{LastUpdated, AUTHORIZED_CALLERS};
-pub use utxo_inscriptions::{get_utxo_inscriptions, UtxoInscription, UtxoInscriptions};
-
-#[derive(CandidType, Deserialize, Serialize, Debug, Clone)]
-struct ApiKey {
-    key: String,
-}
-
-impl Storable for ApiKey {
-    fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(serde_json::to_vec(self).unwrap())
-    }
-
-    fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        serde_json::from_slice(&bytes).unwrap()
-    }
-
-    const BOUND: ic_stable_structures::storable::Bound =
-        ic_stable_structures::storable::Bound::Unbounded;
-}
-
-// Thread-local storage for the API key and request state
+pub use address::AddressType;
+pub use address_brc20::{get_address_brc20, AddressBrc20, Brc20Balance, Brc20TransferInscription};
+pub use address_inscriptions::{
+    get_address_inscriptions, get_addresses_inscriptions, get_all_address_inscriptions,
+    AddressInscription, AddressInscriptions, AllAddressInscriptions, GetAddressInscriptionsArgs,
+    GetAllAddressInscriptionsArgs,
+};
+pub use address_runes::{get_address_runes, AddressRune, AddressRunes, RuneUtxo};
+pub use api_keys::{ApiKeyInfo, ApiKeyState, ApiKeyStatus, ApiKeyUsage};
+pub use auth::{grant_role, list_roles, revoke_role, Role, RoleAssignment};
+pub use cache::{CacheStats, CachedCollectionStats, CachesStats, CollectionStatsCacheEntry};
+pub use common::{LastUpdated, Outpoint, Rarity, INITIAL_ROLES};
+pub use config::{CanisterArg, Config, ConfigUpdate, InitArgs, Network, UpgradeArgs};
+pub use enrichment::RuneMarketData;
+pub use error::CanisterError;
+pub use sat::SatInfo;
+pub use sat_ranges::{get_utxo_sat_ranges, SatRange, UtxoSatRanges};
+pub use spendability::{
+    check_utxos_spendable, SpendabilityVerdict, UnspendableReason, UtxoSpendability,
+};
+pub use utxo_inscriptions::{
+    get_utxo_inscriptions, get_utxos_inscriptions, OutpointInscriptions, UtxoInscription,
+    UtxoInscriptions, UtxoRune,
+};
+
+// Thread-local storage for the request state
 thread_local! {
-    static API_KEY_STORAGE: RefCell<StableCell<ApiKey, DefaultMemoryImpl>> = RefCell::new(
-        StableCell::init(
-            DefaultMemoryImpl::default(),
-            ApiKey { key: String::new() }
-        ).unwrap()
-    );
-
     // Track pending requests to prevent reentrancy
-    static PENDING_REQUESTS: RefCell<BTreeSet<Principal>> = RefCell::new(BTreeSet::new());
+    static PENDING_REQUESTS: RefCell<BTreeSet<Principal>> = const { RefCell::new(BTreeSet::new()) };
 }
 
 // Guard for requests that modify state
@@ -54,14 +63,11 @@ pub struct CallerGuard {
 }
 
 impl CallerGuard {
-    pub fn new(principal: Principal) -> Result<Self, String> {
+    pub fn new(principal: Principal) -> Result<Self, CanisterError> {
         PENDING_REQUESTS.with(|requests| {
             let mut pending = requests.borrow_mut();
             if pending.contains(&principal) {
-                return Err(format!(
-                    "Already processing a request for principal {:?}",
-                    &principal
-                ));
+                return Err(CanisterError::RequestInProgress);
             }
             pending.insert(principal);
             Ok(Self { principal })
@@ -77,56 +83,65 @@ impl Drop for CallerGuard {
     }
 }
 
-// Guard function for authorization
-fn authorized_guard() -> Result<(), String> {
-    let caller = msg_caller();
-    let caller_str = caller.to_text();
-
-    if !AUTHORIZED_CALLERS.iter().any(|&auth| auth == caller_str) {
-        return Err("Unauthorized: Caller not in authorized list".to_string());
+// Checks that the caller holds `role`. Methods returning a `Result` call it
+// first thing, so that callers get `Unauthorized` as a Candid variant.
+fn authorize(role: Role) -> Result<(), CanisterError> {
+    if !auth::has_role(&msg_caller(), role) {
+        return Err(CanisterError::Unauthorized);
     }
     Ok(())
 }
 
-#[query(guard = "authorized_guard")]
-#[candid_method(query)]
-fn get_api_key() -> Result<String, String> {
-    // Authorization is handled by guard function
-    API_KEY_STORAGE.with(|storage| {
-        let key = storage.borrow().get().key.clone();
-        if key.is_empty() {
-            Err("API key not set".to_string())
-        } else {
-            Ok(key)
-        }
-    })
+// Guard functions for the methods that do not return a `Result`, one per
+// role. They reject the call with the message of `Unauthorized`.
+fn role_guard(role: Role) -> Result<(), String> {
+    authorize(role).map_err(|e| e.to_string())
 }
 
-// Update the set_api_key function to use the global constant
-#[update(guard = "authorized_guard")]
-#[candid_method(update)]
-async fn set_api_key(new_key: String) -> Result<(), String> {
-    let caller = msg_caller();
+fn admin_guard() -> Result<(), String> {
+    role_guard(Role::Admin)
+}
 
-    // Input validation
-    if new_key.is_empty() {
-        return Err("API key cannot be empty".to_string());
-    }
+fn operator_guard() -> Result<(), String> {
+    role_guard(Role::Operator)
+}
 
-    if new_key.len() > 1000 {
-        return Err("API key too long (max 1000 characters)".to_string());
+fn reader_guard() -> Result<(), String> {
+    role_guard(Role::Reader)
+}
+
+#[init]
+fn init(arg: Option<CanisterArg>) {
+    let args = match arg {
+        None => InitArgs::default(),
+        Some(CanisterArg::Init(args)) => args,
+        Some(CanisterArg::Upgrade(_)) => ic_cdk::trap("Expected Init arguments on install"),
+    };
+
+    match args.roles {
+        Some(ref roles) => {
+            auth::assign_roles(roles).unwrap_or_else(|e| ic_cdk::trap(e.to_string()))
+        }
+        None => auth::seed_roles(),
     }
+    config::init(args).unwrap_or_else(|e| ic_cdk::trap(e.to_string()));
+    migrations::init();
+}
 
-    // Prevent reentrancy by the same caller
-    let _guard = CallerGuard::new(caller).map_err(|e| format!("Reentrancy protection: {}", e))?;
+#[post_upgrade]
+fn post_upgrade(arg: Option<CanisterArg>) {
+    migrations::migrate();
 
-    API_KEY_STORAGE.with(|storage| {
-        storage
-            .borrow_mut()
-            .set(ApiKey { key: new_key })
-            .map(|_| ())
-            .map_err(|e| format!("Failed to save API key: {:?}", e))
-    })
+    // Invalid arguments trap, which rolls the upgrade back
+    let args = match arg {
+        None => return,
+        Some(CanisterArg::Upgrade(args)) => args,
+        Some(CanisterArg::Init(_)) => ic_cdk::trap("Expected Upgrade arguments on upgrade"),
+    };
+    if let Some(ref roles) = args.roles {
+        auth::assign_roles(roles).unwrap_or_else(|e| ic_cdk::trap(e.to_string()));
+    }
+    config::upgrade(args).unwrap_or_else(|e| ic_cdk::trap(e.to_string()));
 }
 
 #[ic_cdk::query(hidden = true)]
@@ -137,7 +152,6 @@ fn transform(raw: TransformArgs) -> HttpRequestResult {
         status: raw.response.status.clone(),
         body: raw.response.body.clone(),
         headers,
-        ..Default::default()
     };
 
     if res.status == 200u8 {
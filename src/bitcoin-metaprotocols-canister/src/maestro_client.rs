@@ -0,0 +1,135 @@
+use ic_cdk::api::canister_self;
+use ic_cdk::management_canister::{
+    HttpHeader, HttpMethod, HttpRequestArgs, TransformContext, TransformFunc,
+};
+use serde::de::DeserializeOwned;
+
+use crate::api_keys::http_request_with_failover;
+use crate::config::config;
+use crate::error::CanisterError;
+
+// Upper bound on the size of a Maestro response holding a single item, and on
+// the envelope (`last_updated`, `next_cursor`) of a list response
+const MAX_RESPONSE_BYTES: u64 = 5 * 1000;
+
+// Upper bound on the size of one item of a list response. Inscriptions, the
+// largest items requested in lists, take about 400 bytes.
+const MAX_LIST_ITEM_BYTES: u64 = 1000;
+
+/// Typed client for the Maestro API. Every request is authenticated with the
+/// keyring, sized for the response it expects and passed through the
+/// `transform` query so that replicas reach consensus on the response.
+pub struct MaestroClient {
+    base_url: String,
+}
+
+impl Default for MaestroClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MaestroClient {
+    pub fn new() -> Self {
+        Self {
+            base_url: config().maestro_base_url(),
+        }
+    }
+
+    fn url(&self, path: &str, query: &[(&str, &str)]) -> String {
+        let mut url = format!("{}{}", self.base_url, path);
+        for (i, (name, value)) in query.iter().enumerate() {
+            url.push(if i == 0 { '?' } else { '&' });
+            url.push_str(name);
+            url.push('=');
+            url.push_str(&urlencoding::encode(value));
+        }
+        url
+    }
+
+    /// Sends a GET request to `path` (relative to the base URL, starting with
+    /// `/`) and deserializes a successful response body into `T`.
+    pub async fn get_json<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<T, CanisterError> {
+        self.get_json_sized(path, query, MAX_RESPONSE_BYTES).await
+    }
+
+    /// Like `get_json`, for list endpoints answering with up to `count` items.
+    /// Sets the `count` query parameter.
+    pub async fn get_json_list<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+        count: u32,
+    ) -> Result<T, CanisterError> {
+        let count_param = count.to_string();
+        let mut query = query.to_vec();
+        query.push(("count", &count_param));
+
+        let max_response_bytes = MAX_RESPONSE_BYTES + u64::from(count) * MAX_LIST_ITEM_BYTES;
+        self.get_json_sized(path, &query, max_response_bytes).await
+    }
+
+    /// Like `get_json`, for endpoints answering with a whole collection at
+    /// once rather than in pages. Sized for up to `max_items` items of at most
+    /// `item_bytes` each.
+    pub async fn get_json_collection<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+        max_items: u32,
+        item_bytes: u64,
+    ) -> Result<T, CanisterError> {
+        let max_response_bytes = MAX_RESPONSE_BYTES + u64::from(max_items) * item_bytes;
+        self.get_json_sized(path, query, max_response_bytes).await
+    }
+
+    async fn get_json_sized<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+        max_response_bytes: u64,
+    ) -> Result<T, CanisterError> {
+        let url = self.url(path, query);
+
+        let response = http_request_with_failover(|api_key| HttpRequestArgs {
+            url: url.clone(),
+            method: HttpMethod::GET,
+            headers: vec![HttpHeader {
+                name: "api-key".to_string(),
+                value: api_key.to_string(),
+            }],
+            body: None,
+            max_response_bytes: Some(max_response_bytes),
+            transform: Some(TransformContext {
+                function: TransformFunc::new(canister_self(), "transform".to_string()),
+                context: vec![],
+            }),
+        })
+        .await?;
+
+        let raw_body = String::from_utf8_lossy(&response.body);
+        if response.status != 200u8 {
+            ic_cdk::println!(
+                "HTTP status {} from {}: {}",
+                response.status,
+                path,
+                raw_body
+            );
+            let status = u16::try_from(&response.status.0).unwrap_or(0);
+            return Err(if status == 429 {
+                CanisterError::RateLimited
+            } else {
+                CanisterError::UpstreamHttp { status }
+            });
+        }
+
+        serde_json::from_slice(&response.body).map_err(|e| {
+            ic_cdk::println!("Failed to parse {}: {} (body: {})", path, e, raw_body);
+            CanisterError::DecodeError(e.to_string())
+        })
+    }
+}
@@ -0,0 +1,45 @@
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::{DefaultMemoryImpl, Memory as _};
+use std::cell::RefCell;
+
+pub type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+// Memory ids of the stable structures. Never reuse or reorder them.
+pub const ROLES_MEMORY_ID: MemoryId = MemoryId::new(0);
+pub const API_KEYS_MEMORY_ID: MemoryId = MemoryId::new(1);
+pub const CONFIG_MEMORY_ID: MemoryId = MemoryId::new(2);
+pub const COLLECTION_STATS_CACHE_MEMORY_ID: MemoryId = MemoryId::new(3);
+pub const INSCRIPTION_INFO_CACHE_MEMORY_ID: MemoryId = MemoryId::new(4);
+pub const INSCRIPTION_INFO_LRU_MEMORY_ID: MemoryId = MemoryId::new(5);
+pub const SCHEMA_VERSION_MEMORY_ID: MemoryId = MemoryId::new(6);
+pub const RUNE_MARKET_DATA_CACHE_MEMORY_ID: MemoryId = MemoryId::new(7);
+pub const RUNE_METADATA_CACHE_MEMORY_ID: MemoryId = MemoryId::new(8);
+pub const RUNE_METADATA_LRU_MEMORY_ID: MemoryId = MemoryId::new(9);
+
+// Magic bytes written by a `StableCell` at the start of the memory it owns
+const STABLE_CELL_MAGIC: &[u8; 3] = b"SCL";
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+}
+
+pub fn get_memory(id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with(|manager| manager.borrow().get(id))
+}
+
+/// Returns true if the raw stable memory still holds the pre-`MemoryManager`
+/// layout, where a single `StableCell` claimed the whole stable memory.
+///
+/// Must be called before anything touches `MEMORY_MANAGER`, since initializing
+/// the manager overwrites the legacy cell header.
+pub fn has_legacy_layout() -> bool {
+    let memory = DefaultMemoryImpl::default();
+    if memory.size() == 0 {
+        return false;
+    }
+
+    let mut magic = [0; 3];
+    memory.read(0, &mut magic);
+    &magic == STABLE_CELL_MAGIC
+}
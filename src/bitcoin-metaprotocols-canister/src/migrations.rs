@@ -0,0 +1,68 @@
+use ic_stable_structures::{DefaultMemoryImpl, StableCell};
+use std::cell::RefCell;
+
+use crate::api_keys::{self, ApiKey};
+use crate::auth;
+use crate::memory::{self, get_memory, Memory, SCHEMA_VERSION_MEMORY_ID};
+
+// Versions of the stable memory layout:
+// 0: a single `StableCell` holding the API key claims the whole stable memory
+// 1: memory manager, with role assignments, the API keyring and the caches
+pub const CURRENT_SCHEMA_VERSION: u32 = 1;
+
+thread_local! {
+    static SCHEMA_VERSION: RefCell<StableCell<u32, Memory>> = RefCell::new(
+        StableCell::init(get_memory(SCHEMA_VERSION_MEMORY_ID), CURRENT_SCHEMA_VERSION).unwrap()
+    );
+}
+
+fn set_schema_version(version: u32) {
+    SCHEMA_VERSION.with(|cell| {
+        cell.borrow_mut()
+            .set(version)
+            .map(|_| ())
+            .unwrap_or_else(|e| ic_cdk::trap(format!("Failed to save schema version: {:?}", e)));
+    });
+}
+
+/// Marks a fresh install as being on the current layout.
+pub fn init() {
+    set_schema_version(CURRENT_SCHEMA_VERSION);
+}
+
+/// Brings the stable memory from the layout of the previous release to the
+/// current one. Must run first in `post_upgrade`, before anything touches the
+/// memory manager.
+pub fn migrate() {
+    let version = if memory::has_legacy_layout() {
+        migrate_from_legacy_layout();
+        ic_cdk::println!(
+            "Migrated stable memory to schema version {}",
+            CURRENT_SCHEMA_VERSION
+        );
+        CURRENT_SCHEMA_VERSION
+    } else {
+        SCHEMA_VERSION.with(|cell| *cell.borrow().get())
+    };
+
+    if version > CURRENT_SCHEMA_VERSION {
+        ic_cdk::trap(format!(
+            "Stable memory is on schema version {}, newer than {}",
+            version, CURRENT_SCHEMA_VERSION
+        ));
+    }
+    set_schema_version(version);
+}
+
+// Version 0 to 1. The legacy cell is read before the memory manager takes
+// over, since initializing the manager overwrites the cell header. The key
+// then goes straight to the keyring, and the roles are seeded since the
+// legacy layout had no principals in stable memory.
+fn migrate_from_legacy_layout() {
+    let legacy_key = StableCell::init(DefaultMemoryImpl::default(), ApiKey::empty())
+        .map(|cell| cell.get().clone())
+        .unwrap_or_else(|e| ic_cdk::trap(format!("Failed to read legacy API key: {:?}", e)));
+
+    api_keys::migrate_api_key(legacy_key);
+    auth::seed_roles();
+}
@@ -0,0 +1,333 @@
+use candid::{candid_method, CandidType};
+use ic_cdk_macros::*;
+use serde::{Deserialize, Serialize};
+
+use crate::auth::Role;
+use crate::common::Rarity;
+use crate::error::CanisterError;
+
+const COIN_VALUE: u64 = 100_000_000;
+const SUBSIDY_HALVING_INTERVAL: u64 = 210_000;
+const DIFFCHANGE_INTERVAL: u64 = 2016;
+// A cycle ends when a halving and a difficulty adjustment coincide
+const CYCLE_EPOCHS: u64 = 6;
+// The subsidy is zero from this epoch on
+const LAST_EPOCH: u64 = 33;
+
+/// Number of sats that will ever be mined.
+pub const SUPPLY: u64 = epoch_starting_sat(LAST_EPOCH);
+
+const fn subsidy(epoch: u64) -> u64 {
+    (50 * COIN_VALUE) >> epoch
+}
+
+const fn epoch_starting_sat(epoch: u64) -> u64 {
+    let mut starting_sat = 0;
+    let mut e = 0;
+    while e < epoch {
+        starting_sat += subsidy(e) * SUBSIDY_HALVING_INTERVAL;
+        e += 1;
+    }
+    starting_sat
+}
+
+/// Position of a sat in the ordinals numbering, computed without outcalls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Sat(u64);
+
+impl Sat {
+    /// Returns none past the last sat that will ever be mined.
+    pub fn new(ordinal: u64) -> Option<Self> {
+        (ordinal < SUPPLY).then_some(Self(ordinal))
+    }
+
+    pub fn epoch(self) -> u64 {
+        let mut epoch = 0;
+        while epoch_starting_sat(epoch + 1) <= self.0 {
+            epoch += 1;
+        }
+        epoch
+    }
+
+    /// Height of the block whose subsidy mined the sat.
+    pub fn height(self) -> u64 {
+        let epoch = self.epoch();
+        epoch * SUBSIDY_HALVING_INTERVAL + (self.0 - epoch_starting_sat(epoch)) / subsidy(epoch)
+    }
+
+    /// Position of the sat within the subsidy of its block.
+    pub fn offset(self) -> u64 {
+        let epoch = self.epoch();
+        (self.0 - epoch_starting_sat(epoch)) % subsidy(epoch)
+    }
+
+    pub fn cycle(self) -> u64 {
+        self.epoch() / CYCLE_EPOCHS
+    }
+
+    /// Degree notation: cycle°blocks since the halving′blocks since the
+    /// difficulty adjustment″offset‴
+    pub fn degree(self) -> String {
+        let height = self.height();
+        format!(
+            "{}°{}′{}″{}‴",
+            self.cycle(),
+            height % SUBSIDY_HALVING_INTERVAL,
+            height % DIFFCHANGE_INTERVAL,
+            self.offset()
+        )
+    }
+
+    /// Decimal notation: height.offset
+    pub fn decimal(self) -> String {
+        format!("{}.{}", self.height(), self.offset())
+    }
+
+    /// Name of the sat, from "nvtdijuwxlp" for the first one to "a" for the last one.
+    pub fn name(self) -> String {
+        let mut x = SUPPLY - self.0;
+        let mut name = Vec::new();
+        while x > 0 {
+            name.push(b'a' + ((x - 1) % 26) as u8);
+            x = (x - 1) / 26;
+        }
+        name.reverse();
+        String::from_utf8(name).unwrap()
+    }
+
+    pub fn rarity(self) -> Rarity {
+        if self.offset() != 0 {
+            return Rarity::Common;
+        }
+        block_rarity(self.height())
+    }
+}
+
+// Rarity of the first sat of the block at `height`
+fn block_rarity(height: u64) -> Rarity {
+    if height == 0 {
+        Rarity::Mythic
+    } else if height.is_multiple_of(CYCLE_EPOCHS * SUBSIDY_HALVING_INTERVAL) {
+        Rarity::Legendary
+    } else if height.is_multiple_of(SUBSIDY_HALVING_INTERVAL) {
+        Rarity::Epic
+    } else if height.is_multiple_of(DIFFCHANGE_INTERVAL) {
+        Rarity::Rare
+    } else {
+        Rarity::Uncommon
+    }
+}
+
+// True if a multiple of `m` lies within `first..=last`
+fn contains_multiple(first: u64, last: u64, m: u64) -> bool {
+    last / m * m >= first
+}
+
+/// Rarity of the rarest sat within `start..end`, none if the range is empty or
+/// past the supply. Only the first sat of a block can be rarer than common, so
+/// this looks at the blocks starting within the range rather than every sat.
+pub fn rarest_in_range(start: u64, end: u64) -> Option<Rarity> {
+    if start >= end || end > SUPPLY {
+        return None;
+    }
+
+    let first = Sat(start);
+    let first_height = first.height() + u64::from(first.offset() != 0);
+    let last_height = Sat(end - 1).height();
+    if first_height > last_height {
+        return Some(Rarity::Common);
+    }
+
+    Some(if first_height == 0 {
+        Rarity::Mythic
+    } else if contains_multiple(
+        first_height,
+        last_height,
+        CYCLE_EPOCHS * SUBSIDY_HALVING_INTERVAL,
+    ) {
+        Rarity::Legendary
+    } else if contains_multiple(first_height, last_height, SUBSIDY_HALVING_INTERVAL) {
+        Rarity::Epic
+    } else if contains_multiple(first_height, last_height, DIFFCHANGE_INTERVAL) {
+        Rarity::Rare
+    } else {
+        Rarity::Uncommon
+    })
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+pub struct SatInfo {
+    pub ordinal: u64,
+    pub height: u64,
+    pub epoch: u64,
+    pub cycle: u64,
+    // Position within the subsidy of its block
+    pub offset: u64,
+    pub degree: String,
+    pub decimal: String,
+    pub name: String,
+    pub rarity: Rarity,
+}
+
+#[query]
+#[candid_method(query)]
+fn sat_info(ordinal: u64) -> Result<SatInfo, CanisterError> {
+    crate::authorize(Role::Reader)?;
+
+    let sat = Sat::new(ordinal)
+        .ok_or_else(|| CanisterError::InvalidInput(format!("ordinal must be below {}", SUPPLY)))?;
+
+    Ok(SatInfo {
+        ordinal,
+        height: sat.height(),
+        epoch: sat.epoch(),
+        cycle: sat.cycle(),
+        offset: sat.offset(),
+        degree: sat.degree(),
+        decimal: sat.decimal(),
+        name: sat.name(),
+        rarity: sat.rarity(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Rarest sat of `start..end`, found by checking the sats of every block the
+    // range touches. Only the first sat of a block can be rarer than common, so
+    // the first and last sat of each block within the range cover all rarities.
+    fn brute_force_rarest(start: u64, end: u64) -> Option<Rarity> {
+        if start >= end || end > SUPPLY {
+            return None;
+        }
+
+        let mut rarest = None;
+        for height in Sat(start).height()..=Sat(end - 1).height() {
+            let block_start = block_start(height);
+            let first = start.max(block_start);
+            let last = (end - 1).min(block_start + block_subsidy(height) - 1);
+            rarest = rarest.max(Some(Sat(first).rarity().max(Sat(last).rarity())));
+        }
+        rarest
+    }
+
+    fn block_subsidy(height: u64) -> u64 {
+        subsidy(height / SUBSIDY_HALVING_INTERVAL)
+    }
+
+    fn block_start(height: u64) -> u64 {
+        let epoch = height / SUBSIDY_HALVING_INTERVAL;
+        epoch_starting_sat(epoch) + (height % SUBSIDY_HALVING_INTERVAL) * subsidy(epoch)
+    }
+
+    #[test]
+    fn supply() {
+        assert_eq!(SUPPLY, 2_099_999_997_690_000);
+        assert_eq!(Sat::new(SUPPLY), None);
+        assert_eq!(Sat::new(SUPPLY - 1), Some(Sat(SUPPLY - 1)));
+    }
+
+    #[test]
+    fn known_sats() {
+        let cases = [
+            // (ordinal, height, epoch, name, degree, decimal, rarity)
+            (0, 0, 0, "nvtdijuwxlp", "0°0′0″0‴", "0.0", Rarity::Mythic),
+            (1, 0, 0, "nvtdijuwxlo", "0°0′0″1‴", "0.1", Rarity::Common),
+            (
+                5_000_000_000,
+                1,
+                0,
+                "nvtcsezkbth",
+                "0°1′1″0‴",
+                "1.0",
+                Rarity::Uncommon,
+            ),
+            (
+                10_080_000_000_000,
+                2016,
+                0,
+                "ntwwidfrzxh",
+                "0°2016′0″0‴",
+                "2016.0",
+                Rarity::Rare,
+            ),
+            (
+                1_050_000_000_000_000,
+                210_000,
+                1,
+                "gkjbdrhkfqf",
+                "0°0′336″0‴",
+                "210000.0",
+                Rarity::Epic,
+            ),
+            (
+                2_067_187_500_000_000,
+                1_260_000,
+                6,
+                "fachfvytgb",
+                "1°0′0″0‴",
+                "1260000.0",
+                Rarity::Legendary,
+            ),
+            (
+                SUPPLY - 1,
+                6_929_999,
+                32,
+                "a",
+                "5°209999′1007″0‴",
+                "6929999.0",
+                Rarity::Uncommon,
+            ),
+        ];
+
+        for (ordinal, height, epoch, name, degree, decimal, rarity) in cases {
+            let sat = Sat::new(ordinal).unwrap();
+            assert_eq!(sat.height(), height, "height of {}", ordinal);
+            assert_eq!(sat.epoch(), epoch, "epoch of {}", ordinal);
+            assert_eq!(sat.name(), name, "name of {}", ordinal);
+            assert_eq!(sat.degree(), degree, "degree of {}", ordinal);
+            assert_eq!(sat.decimal(), decimal, "decimal of {}", ordinal);
+            assert_eq!(sat.rarity(), rarity, "rarity of {}", ordinal);
+        }
+    }
+
+    #[test]
+    fn rarest_in_empty_or_past_supply_range() {
+        assert_eq!(rarest_in_range(3, 3), None);
+        assert_eq!(rarest_in_range(5, 3), None);
+        assert_eq!(rarest_in_range(SUPPLY, SUPPLY + 1), None);
+        assert_eq!(rarest_in_range(SUPPLY - 1, SUPPLY + 1), None);
+    }
+
+    #[test]
+    fn rarest_in_range_matches_brute_force() {
+        let heights = [
+            0, 1, 2, 2015, 2016, 2017, 209_999, 210_000, 210_001, 1_260_000, 6_929_000, 6_929_999,
+        ];
+        let mut ranges = vec![(0, 1), (1, 5_000_000_000), (SUPPLY - 5000, SUPPLY)];
+        for height in heights {
+            // From a few sats before the block start to past the next
+            // difficulty adjustment
+            let sat = block_start(height);
+            let subsidy = block_subsidy(height);
+            for before in [0, 1, 2, subsidy] {
+                for after in [1, 2, subsidy, subsidy + 1, 2017 * subsidy] {
+                    let start = sat.saturating_sub(before);
+                    let end = (sat + after).min(SUPPLY);
+                    ranges.push((start, end));
+                }
+            }
+        }
+
+        for (start, end) in ranges {
+            assert_eq!(
+                rarest_in_range(start, end),
+                brute_force_rarest(start, end),
+                "rarest in {}..{}",
+                start,
+                end
+            );
+        }
+    }
+}
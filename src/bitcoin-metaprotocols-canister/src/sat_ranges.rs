@@ -0,0 +1,190 @@
+use candid::{candid_method, CandidType};
+use ic_cdk_macros::*;
+use serde::{Deserialize, Serialize};
+
+use crate::auth::Role;
+use crate::common::{LastUpdated, Rarity};
+use crate::error::CanisterError;
+use crate::maestro_client::MaestroClient;
+use crate::sat::rarest_in_range;
+use crate::validation::validate_txid;
+
+// Most sat ranges a single output is expected to hold, e.g. one that
+// consolidated many inputs. Outputs with more ranges fail to fetch.
+const MAX_SAT_RANGES: u32 = 2000;
+
+// Upper bound on the size of one sat range in a Maestro response, with its
+// ordinal numbers at full width and a few satributes
+const MAX_SAT_RANGE_BYTES: u64 = 150;
+
+// Sat range-specific types
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+pub struct MaestroSatRange {
+    pub start: u64,
+    // Exclusive
+    pub end: u64,
+    pub rarity: Rarity,
+    #[serde(default)]
+    pub satributes: Vec<String>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+pub struct MaestroSatRangesResponse {
+    pub data: Vec<MaestroSatRange>,
+    pub last_updated: LastUpdated,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+pub struct SatRange {
+    // Ordinal numbers of the first sat and of the sat after the last one
+    pub start: u64,
+    pub end: u64,
+    // Position of the first sat within the output
+    pub offset: u64,
+    // Rarity of the rarest sat in the range
+    pub rarity: Rarity,
+    // Notable properties such as "pizza", "block_9", "vintage" or "palindrome"
+    pub satributes: Vec<String>,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+pub struct UtxoSatRanges {
+    pub data: Vec<SatRange>,
+    // Highest rarity among the sats of the output
+    pub rarest: Rarity,
+    // True if the output holds a sat rarer than common or with a satribute
+    pub has_notable_sats: bool,
+    pub last_updated: LastUpdated,
+}
+
+#[update]
+#[candid_method(update)]
+pub async fn get_utxo_sat_ranges(
+    tx_hash: String,
+    output_index: u32,
+) -> Result<UtxoSatRanges, CanisterError> {
+    crate::authorize(Role::Reader)?;
+
+    validate_txid(&tx_hash)?;
+
+    let client = MaestroClient::new();
+    fetch_utxo_sat_ranges(&client, &tx_hash, output_index).await
+}
+
+/// Fetches the sat ranges of a transaction output, with their rarity
+/// computed locally.
+pub async fn fetch_utxo_sat_ranges(
+    client: &MaestroClient,
+    tx_hash: &str,
+    output_index: u32,
+) -> Result<UtxoSatRanges, CanisterError> {
+    let sat_ranges_maestro_response: MaestroSatRangesResponse = client
+        .get_json_collection(
+            &format!(
+                "/transactions/{}/outputs/{}/sat_ranges",
+                urlencoding::encode(tx_hash),
+                output_index
+            ),
+            &[],
+            MAX_SAT_RANGES,
+            MAX_SAT_RANGE_BYTES,
+        )
+        .await?;
+
+    Ok(utxo_sat_ranges(sat_ranges_maestro_response))
+}
+
+fn utxo_sat_ranges(sat_ranges_maestro_response: MaestroSatRangesResponse) -> UtxoSatRanges {
+    let mut offset = 0;
+    let final_result: Vec<SatRange> = sat_ranges_maestro_response
+        .data
+        .into_iter()
+        .map(|range| {
+            // Rarity follows from the ordinal numbers, so Maestro's is only checked
+            let rarity = rarest_in_range(range.start, range.end).unwrap_or(range.rarity);
+            if rarity != range.rarity {
+                ic_cdk::println!(
+                    "Rarity mismatch for sats {}..{}: {:?} from Maestro, {:?} computed",
+                    range.start,
+                    range.end,
+                    range.rarity,
+                    rarity
+                );
+            }
+            let sat_range = SatRange {
+                start: range.start,
+                end: range.end,
+                offset,
+                rarity,
+                satributes: range.satributes,
+            };
+            offset += range.end.saturating_sub(range.start);
+            sat_range
+        })
+        .collect();
+
+    let rarest = final_result
+        .iter()
+        .map(|range| range.rarity)
+        .max()
+        .unwrap_or(Rarity::Common);
+    let has_notable_sats = rarest > Rarity::Common
+        || final_result
+            .iter()
+            .any(|range| !range.satributes.is_empty());
+
+    UtxoSatRanges {
+        data: final_result,
+        rarest,
+        has_notable_sats,
+        last_updated: sat_ranges_maestro_response.last_updated,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Response of an output that consolidated many inputs, one single-sat range
+    // per input. The ranges are common sats of the block at height 1260000, so
+    // that their ordinals are as wide as any.
+    fn many_ranges_response(count: u64, satributes: &str) -> String {
+        let ranges: Vec<String> = (0..count)
+            .map(|i| {
+                let start = 2_067_187_500_000_001 + 2 * i;
+                format!(
+                    r#"{{"start":{},"end":{},"rarity":"common","satributes":[{}]}}"#,
+                    start,
+                    start + 1,
+                    satributes
+                )
+            })
+            .collect();
+        format!(
+            r#"{{"data":[{}],"last_updated":{{"block_hash":"{}","block_height":900000}}}}"#,
+            ranges.join(","),
+            "0".repeat(64)
+        )
+    }
+
+    #[test]
+    fn many_ranges_fit_the_response_size() {
+        let body =
+            many_ranges_response(MAX_SAT_RANGES.into(), r#""palindrome","vintage","block_9""#);
+        assert!(body.len() as u64 <= 5 * 1000 + u64::from(MAX_SAT_RANGES) * MAX_SAT_RANGE_BYTES);
+    }
+
+    #[test]
+    fn parses_many_ranges() {
+        let response: MaestroSatRangesResponse =
+            serde_json::from_str(&many_ranges_response(MAX_SAT_RANGES.into(), "")).unwrap();
+        let sat_ranges = utxo_sat_ranges(response);
+
+        assert_eq!(sat_ranges.data.len(), MAX_SAT_RANGES as usize);
+        assert_eq!(sat_ranges.rarest, Rarity::Common);
+        assert!(!sat_ranges.has_notable_sats);
+        for (i, range) in sat_ranges.data.iter().enumerate() {
+            assert_eq!(range.offset, i as u64);
+        }
+    }
+}
@@ -0,0 +1,307 @@
+use candid::{candid_method, CandidType};
+use futures::stream::{self, StreamExt};
+use ic_cdk_macros::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::address_brc20::fetch_transfer_inscriptions;
+use crate::auth::Role;
+use crate::common::{LastUpdated, Outpoint, Rarity, MAX_OUTPOINTS};
+use crate::config::config;
+use crate::error::CanisterError;
+use crate::maestro_client::MaestroClient;
+use crate::sat_ranges::{fetch_utxo_sat_ranges, UtxoSatRanges};
+use crate::utxo_inscriptions::{fetch_tx_out, MaestroTxOutIntoResponse};
+use crate::validation::validate_txid;
+
+/// Why an output should not be spent as plain BTC.
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+pub enum UnspendableReason {
+    /// The output was already spent by this transaction
+    Spent {
+        spending_tx: String,
+    },
+    Inscriptions {
+        inscription_ids: Vec<String>,
+    },
+    /// Inscriptions of the output that are BRC-20 transfers not sent yet
+    Brc20Transfers {
+        inscription_ids: Vec<String>,
+    },
+    Runes {
+        rune_ids: Vec<String>,
+    },
+    /// The output holds sats rarer than common or with a satribute
+    NotableSats {
+        rarest: Rarity,
+        satributes: Vec<String>,
+    },
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+pub struct SpendabilityVerdict {
+    // True if there is no reason not to spend the output
+    pub spendable: bool,
+    pub reasons: Vec<UnspendableReason>,
+    // True if the inscriptions of the output may include BRC-20 transfers not
+    // listed under `Brc20Transfers`, because the address holds more transfer
+    // inscriptions than fetched or their lookup failed
+    pub brc20_transfers_incomplete: bool,
+    pub satoshis: String,
+    pub last_updated: LastUpdated,
+}
+
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+pub struct UtxoSpendability {
+    pub outpoint: Outpoint,
+    // An error means the output could not be checked and should not be spent
+    pub verdict: Result<SpendabilityVerdict, CanisterError>,
+}
+
+// Transfer inscriptions of an address that have not been sent yet
+struct Brc20Transfers {
+    inscription_ids: BTreeSet<String>,
+    // True if the address holds more transfer inscriptions than fetched
+    truncated: bool,
+}
+
+/// Checks whether outputs can be spent as plain BTC, that is whether they hold
+/// no inscriptions, runes or notable sats and are still unspent. The transfer
+/// inscriptions of an address are fetched once, however many of its outputs
+/// are checked.
+#[update]
+#[candid_method(update)]
+pub async fn check_utxos_spendable(
+    outpoints: Vec<Outpoint>,
+) -> Result<Vec<UtxoSpendability>, CanisterError> {
+    crate::authorize(Role::Reader)?;
+
+    if outpoints.len() > MAX_OUTPOINTS {
+        return Err(CanisterError::InvalidInput(format!(
+            "at most {} outpoints can be checked per call",
+            MAX_OUTPOINTS
+        )));
+    }
+
+    let client = MaestroClient::new();
+    let client = &client;
+    let max_concurrent_outcalls = config().max_concurrent_outcalls as usize;
+
+    let tx_out_responses: Vec<Result<MaestroTxOutIntoResponse, CanisterError>> =
+        stream::iter(&outpoints)
+            .map(|outpoint| async move {
+                validate_txid(&outpoint.txid)?;
+                fetch_tx_out(client, &outpoint.txid, outpoint.vout).await
+            })
+            .buffered(max_concurrent_outcalls)
+            .collect()
+            .await;
+
+    // Only unspent inscribed outputs can hold BRC-20 transfers
+    let addresses: BTreeSet<String> = tx_out_responses
+        .iter()
+        .flatten()
+        .map(|response| &response.data)
+        .filter(|tx_out| tx_out.spending_tx.is_none() && !tx_out.inscriptions.is_empty())
+        .filter_map(|tx_out| tx_out.address.clone())
+        .collect();
+    let brc20_transfers: BTreeMap<String, Option<Brc20Transfers>> = stream::iter(addresses)
+        .map(|address| async move {
+            let transfers = find_brc20_transfers(client, &address).await;
+            (address, transfers)
+        })
+        .buffered(max_concurrent_outcalls)
+        .collect()
+        .await;
+    let brc20_transfers = &brc20_transfers;
+
+    Ok(stream::iter(outpoints.into_iter().zip(tx_out_responses))
+        .map(|(outpoint, tx_out_response)| async move {
+            let verdict = match tx_out_response {
+                Ok(tx_out_response) => {
+                    check_utxo(client, &outpoint, tx_out_response, brc20_transfers).await
+                }
+                Err(e) => Err(e),
+            };
+            UtxoSpendability { outpoint, verdict }
+        })
+        .buffered(max_concurrent_outcalls)
+        .collect()
+        .await)
+}
+
+// Spent outputs are reported as such without further outcalls. Sat ranges are
+// looked up for every other output, since any of them can hold rare sats.
+async fn check_utxo(
+    client: &MaestroClient,
+    outpoint: &Outpoint,
+    tx_out_response: MaestroTxOutIntoResponse,
+    brc20_transfers: &BTreeMap<String, Option<Brc20Transfers>>,
+) -> Result<SpendabilityVerdict, CanisterError> {
+    let tx_out = tx_out_response.data;
+
+    let mut reasons = Vec::new();
+    if let Some(spending_tx) = tx_out.spending_tx {
+        reasons.push(UnspendableReason::Spent { spending_tx });
+        return Ok(SpendabilityVerdict {
+            spendable: false,
+            reasons,
+            brc20_transfers_incomplete: false,
+            satoshis: tx_out.satoshis,
+            last_updated: tx_out_response.last_updated,
+        });
+    }
+
+    let inscription_ids: Vec<String> = tx_out
+        .inscriptions
+        .into_iter()
+        .map(|inscription| inscription.inscription_id)
+        .collect();
+    let mut brc20_transfers_incomplete = false;
+    if !inscription_ids.is_empty() {
+        let transfers = tx_out
+            .address
+            .as_ref()
+            .and_then(|address| brc20_transfers.get(address));
+        let transfer_ids: Vec<String> = match transfers {
+            Some(Some(transfers)) => {
+                brc20_transfers_incomplete = transfers.truncated;
+                inscription_ids
+                    .iter()
+                    .filter(|inscription_id| transfers.inscription_ids.contains(*inscription_id))
+                    .cloned()
+                    .collect()
+            }
+            // The lookup failed
+            Some(None) => {
+                brc20_transfers_incomplete = true;
+                Vec::new()
+            }
+            // Outputs without an address hold no BRC-20 balance
+            None => Vec::new(),
+        };
+        reasons.push(UnspendableReason::Inscriptions { inscription_ids });
+        if !transfer_ids.is_empty() {
+            reasons.push(UnspendableReason::Brc20Transfers {
+                inscription_ids: transfer_ids,
+            });
+        }
+    }
+
+    if !tx_out.runes.is_empty() {
+        reasons.push(UnspendableReason::Runes {
+            rune_ids: tx_out.runes.into_iter().map(|rune| rune.rune_id).collect(),
+        });
+    }
+
+    // Not knowing the sats of an output is only fatal if nothing else rules it out
+    match fetch_utxo_sat_ranges(client, &outpoint.txid, outpoint.vout).await {
+        Ok(sat_ranges) => reasons.extend(notable_sats_reason(sat_ranges)),
+        Err(e) if reasons.is_empty() => return Err(e),
+        Err(e) => ic_cdk::println!(
+            "Failed to fetch sat ranges of {}:{}: {}",
+            outpoint.txid,
+            outpoint.vout,
+            e
+        ),
+    }
+
+    Ok(SpendabilityVerdict {
+        spendable: reasons.is_empty(),
+        reasons,
+        brc20_transfers_incomplete,
+        satoshis: tx_out.satoshis,
+        last_updated: tx_out_response.last_updated,
+    })
+}
+
+fn notable_sats_reason(sat_ranges: UtxoSatRanges) -> Option<UnspendableReason> {
+    if !sat_ranges.has_notable_sats {
+        return None;
+    }
+
+    let mut satributes: Vec<String> = sat_ranges
+        .data
+        .into_iter()
+        .flat_map(|range| range.satributes)
+        .collect();
+    satributes.sort();
+    satributes.dedup();
+    Some(UnspendableReason::NotableSats {
+        rarest: sat_ranges.rarest,
+        satributes,
+    })
+}
+
+// The output's inscriptions are already reported, so a failed lookup only
+// loses the BRC-20 detail
+async fn find_brc20_transfers(client: &MaestroClient, address: &str) -> Option<Brc20Transfers> {
+    match fetch_transfer_inscriptions(client, address).await {
+        Ok(transferable_response) => Some(Brc20Transfers {
+            inscription_ids: transferable_response
+                .data
+                .into_iter()
+                .map(|inscription| inscription.inscription_id)
+                .collect(),
+            truncated: transferable_response.next_cursor.is_some(),
+        }),
+        Err(e) => {
+            ic_cdk::println!(
+                "Failed to fetch BRC-20 transfer inscriptions of {}: {}",
+                address,
+                e
+            );
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sat_ranges::SatRange;
+
+    // Output that consolidated many inputs, one common single-sat range per input
+    fn many_ranges(count: u64) -> UtxoSatRanges {
+        UtxoSatRanges {
+            data: (0..count)
+                .map(|i| SatRange {
+                    start: 1 + 2 * i,
+                    end: 2 + 2 * i,
+                    offset: i,
+                    rarity: Rarity::Common,
+                    satributes: Vec::new(),
+                })
+                .collect(),
+            rarest: Rarity::Common,
+            has_notable_sats: false,
+            last_updated: LastUpdated {
+                block_hash: "0".repeat(64),
+                block_height: 900_000,
+            },
+        }
+    }
+
+    #[test]
+    fn many_plain_ranges_are_spendable() {
+        assert!(notable_sats_reason(many_ranges(2000)).is_none());
+    }
+
+    #[test]
+    fn notable_ranges_are_reported_once() {
+        let mut sat_ranges = many_ranges(2000);
+        sat_ranges.data[10].satributes = vec!["vintage".to_string(), "pizza".to_string()];
+        sat_ranges.data[1500].satributes = vec!["pizza".to_string()];
+        sat_ranges.data[1999].rarity = Rarity::Uncommon;
+        sat_ranges.rarest = Rarity::Uncommon;
+        sat_ranges.has_notable_sats = true;
+
+        match notable_sats_reason(sat_ranges) {
+            Some(UnspendableReason::NotableSats { rarest, satributes }) => {
+                assert_eq!(rarest, Rarity::Uncommon);
+                assert_eq!(satributes, ["pizza", "vintage"]);
+            }
+            reason => panic!("unexpected reason {:?}", reason),
+        }
+    }
+}
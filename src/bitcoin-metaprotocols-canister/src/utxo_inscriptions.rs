@@ -1,15 +1,18 @@
 use candid::{candid_method, CandidType};
-use ic_cdk::api::canister_self;
-use ic_cdk::management_canister::{
-    http_request, HttpHeader, HttpMethod, HttpRequestArgs, TransformContext, TransformFunc,
-};
+use futures::stream::{self, StreamExt};
 use ic_cdk_macros::*;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
 
-use crate::common::{
-    check_authorization, LastUpdated, MaestroInscriptionInfoResponse, MaestroOmbColorGroup,
-    BASE_URL,
+use crate::auth::Role;
+use crate::common::{LastUpdated, Outpoint, MAX_OUTPOINTS};
+use crate::config::config;
+use crate::enrichment::{
+    enrich_inscriptions, enrich_runes, InscriptionEnrichment, RuneEnrichment, RuneMarketData,
 };
+use crate::error::CanisterError;
+use crate::maestro_client::MaestroClient;
+use crate::validation::validate_txid;
 
 // UTXO-specific types
 #[derive(CandidType, Deserialize, Serialize, Debug)]
@@ -49,146 +52,196 @@ pub struct UtxoInscription {
     pub omb_floor_price: Option<i64>,
 }
 
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+pub struct UtxoRune {
+    pub rune_id: String,
+    pub amount: String,
+    // Rune metadata, none if it could not be fetched
+    pub spaced_name: Option<String>,
+    pub symbol: Option<String>,
+    pub divisibility: Option<u8>,
+    // None if disabled or if it could not be fetched
+    pub market_data: Option<RuneMarketData>,
+}
+
 #[derive(CandidType, Deserialize, Serialize, Debug)]
 pub struct UtxoInscriptions {
     pub data: Vec<UtxoInscription>,
+    pub runes: Vec<UtxoRune>,
     pub last_updated: LastUpdated,
     pub next_cursor: Option<String>,
 }
 
+#[derive(CandidType, Deserialize, Serialize, Debug)]
+pub struct OutpointInscriptions {
+    pub outpoint: Outpoint,
+    pub result: Result<UtxoInscriptions, CanisterError>,
+}
+
+/// Fetches a transaction output with the inscriptions and runes it carries.
+pub async fn fetch_tx_out(
+    client: &MaestroClient,
+    tx_hash: &str,
+    output_index: u32,
+) -> Result<MaestroTxOutIntoResponse, CanisterError> {
+    client
+        .get_json(
+            &format!(
+                "/transactions/{}/outputs/{}",
+                urlencoding::encode(tx_hash),
+                output_index
+            ),
+            &[],
+        )
+        .await
+}
+
 #[update]
 #[candid_method(update)]
 pub async fn get_utxo_inscriptions(
     tx_hash: String,
-    output_index: String,
-) -> Result<UtxoInscriptions, String> {
-    check_authorization()?;
-
-    let api_key = crate::get_api_key()?;
-
-    let utxo_inscriptions_maestro_url = format!(
-        "{}/transactions/{}/outputs/{}",
-        BASE_URL, tx_hash, output_index
-    );
-
-    let utxo_inscriptions_maestro_request = HttpRequestArgs {
-        url: utxo_inscriptions_maestro_url,
-        method: HttpMethod::GET,
-        headers: vec![HttpHeader {
-            name: "api-key".to_string(),
-            value: api_key.clone(),
-        }],
-        body: None,
-        max_response_bytes: Some(5 * 1000), // 5000 KB
-        transform: Some(TransformContext {
-            function: TransformFunc::new(canister_self(), "transform".to_string()),
-            context: vec![],
-        }),
-    };
-
-    match http_request(&utxo_inscriptions_maestro_request).await {
-        Ok(response) => {
-            let raw_body = String::from_utf8_lossy(&response.body);
-            ic_cdk::println!("HTTP response body: {}", raw_body);
-
-            let maestro_tx_out_into_response: MaestroTxOutIntoResponse =
-                serde_json::from_slice(&response.body)
-                    .map_err(|e| format!("Failed to parse: {} (body: {})", e, raw_body))?;
-
-            let mut final_result: Vec<UtxoInscription> = Vec::new();
-
-            for inscription in maestro_tx_out_into_response.data.inscriptions {
-                let inscription_info_url = format!(
-                    "{}/assets/inscriptions/{}",
-                    BASE_URL, inscription.inscription_id
-                );
-
-                let inscription_info_request = HttpRequestArgs {
-                    url: inscription_info_url,
-                    method: HttpMethod::GET,
-                    headers: vec![HttpHeader {
-                        name: "api-key".to_string(),
-                        value: api_key.clone(),
-                    }],
-                    body: None,
-                    max_response_bytes: Some(5 * 1000),
-                    transform: Some(TransformContext {
-                        function: TransformFunc::new(canister_self(), "transform".to_string()),
-                        context: vec![],
-                    }),
-                };
-
-                let collection_symbol = match http_request(&inscription_info_request).await {
-                    Ok(inscription_info_response) => {
-                        match serde_json::from_slice::<MaestroInscriptionInfoResponse>(
-                            &inscription_info_response.body,
-                        ) {
-                            Ok(info_response) => info_response.data.collection_symbol,
-                            Err(e) => {
-                                ic_cdk::println!(
-                                    "Failed to parse MaestroInscriptionInfoResponse: {} (body: {})",
-                                    e,
-                                    String::from_utf8_lossy(&inscription_info_response.body)
-                                );
-                                None
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        ic_cdk::println!(
-                            "Failed to fetch inscription info for {}: {:?}",
-                            inscription.inscription_id,
-                            e
-                        );
-                        None
-                    }
-                };
-
-                // Fetch OMB color group
-                let omb_color_group_url = format!(
-                    "{}/assets/inscriptions/{}/omb_color_group",
-                    BASE_URL, inscription.inscription_id
-                );
-                let omb_color_group_request = HttpRequestArgs {
-                    url: omb_color_group_url,
-                    method: HttpMethod::GET,
-                    headers: vec![HttpHeader {
-                        name: "api-key".to_string(),
-                        value: api_key.clone(),
-                    }],
-                    body: None,
-                    max_response_bytes: Some(5 * 1000),
-                    transform: Some(TransformContext {
-                        function: TransformFunc::new(canister_self(), "transform".to_string()),
-                        context: vec![],
-                    }),
-                };
-                let (omb_color, omb_floor_price) = match http_request(&omb_color_group_request)
-                    .await
-                {
-                    Ok(omb_response) => {
-                        match serde_json::from_slice::<MaestroOmbColorGroup>(&omb_response.body) {
-                            Ok(omb) => (Some(omb.data.omb_color), Some(omb.data.omb_floor_price)),
-                            Err(_) => (None, None),
-                        }
-                    }
-                    Err(_) => (None, None),
-                };
-
-                final_result.push(UtxoInscription {
-                    inscription_id: inscription.inscription_id,
-                    collection_symbol,
-                    omb_color,
-                    omb_floor_price,
-                });
-            }
+    output_index: u32,
+) -> Result<UtxoInscriptions, CanisterError> {
+    crate::authorize(Role::Reader)?;
 
-            Ok(UtxoInscriptions {
-                data: final_result,
-                last_updated: maestro_tx_out_into_response.last_updated,
-                next_cursor: maestro_tx_out_into_response.next_cursor,
-            })
-        }
-        Err(e) => Err(format!("HTTP error: {:?}", e)),
+    validate_txid(&tx_hash)?;
+
+    let client = MaestroClient::new();
+
+    let maestro_tx_out_into_response = fetch_tx_out(&client, &tx_hash, output_index).await?;
+    let enrichments = enrich_tx_outs(&client, [&maestro_tx_out_into_response.data]).await;
+
+    Ok(utxo_inscriptions(
+        maestro_tx_out_into_response,
+        &enrichments,
+    ))
+}
+
+/// Looks up the inscriptions and runes of several outputs in one call. An
+/// inscription or rune held by several outputs is enriched once. An outpoint
+/// that fails only fails its own result.
+#[update]
+#[candid_method(update)]
+pub async fn get_utxos_inscriptions(
+    outpoints: Vec<Outpoint>,
+) -> Result<Vec<OutpointInscriptions>, CanisterError> {
+    crate::authorize(Role::Reader)?;
+
+    if outpoints.len() > MAX_OUTPOINTS {
+        return Err(CanisterError::InvalidInput(format!(
+            "at most {} outpoints can be looked up per call",
+            MAX_OUTPOINTS
+        )));
+    }
+
+    let client = MaestroClient::new();
+    let client = &client;
+
+    let responses: Vec<Result<MaestroTxOutIntoResponse, CanisterError>> = stream::iter(&outpoints)
+        .map(|outpoint| async move {
+            validate_txid(&outpoint.txid)?;
+            fetch_tx_out(client, &outpoint.txid, outpoint.vout).await
+        })
+        .buffered(config().max_concurrent_outcalls as usize)
+        .collect()
+        .await;
+
+    let enrichments = enrich_tx_outs(
+        client,
+        responses.iter().flatten().map(|response| &response.data),
+    )
+    .await;
+
+    Ok(outpoints
+        .into_iter()
+        .zip(responses)
+        .map(|(outpoint, response)| OutpointInscriptions {
+            outpoint,
+            result: response.map(|response| utxo_inscriptions(response, &enrichments)),
+        })
+        .collect())
+}
+
+// Enrichments of the inscriptions and runes of one or more outputs, keyed by
+// inscription and rune id
+struct TxOutEnrichments {
+    inscriptions: BTreeMap<String, InscriptionEnrichment>,
+    runes: BTreeMap<String, RuneEnrichment>,
+}
+
+// Each inscription and rune is enriched once, however many outputs carry it
+async fn enrich_tx_outs<'a>(
+    client: &MaestroClient,
+    tx_outs: impl IntoIterator<Item = &'a MaestroTxOut>,
+) -> TxOutEnrichments {
+    let mut inscription_ids = BTreeSet::new();
+    let mut rune_ids = BTreeSet::new();
+    for tx_out in tx_outs {
+        inscription_ids.extend(
+            tx_out
+                .inscriptions
+                .iter()
+                .map(|inscription| inscription.inscription_id.clone()),
+        );
+        rune_ids.extend(tx_out.runes.iter().map(|rune| rune.rune_id.clone()));
+    }
+    let inscription_ids: Vec<String> = inscription_ids.into_iter().collect();
+    let rune_ids: Vec<String> = rune_ids.into_iter().collect();
+
+    let inscription_enrichments = enrich_inscriptions(client, inscription_ids.clone(), false).await;
+    let rune_enrichments = enrich_runes(client, rune_ids.clone()).await;
+
+    TxOutEnrichments {
+        inscriptions: inscription_ids
+            .into_iter()
+            .zip(inscription_enrichments)
+            .collect(),
+        runes: rune_ids.into_iter().zip(rune_enrichments).collect(),
+    }
+}
+
+fn utxo_inscriptions(
+    maestro_tx_out_into_response: MaestroTxOutIntoResponse,
+    enrichments: &TxOutEnrichments,
+) -> UtxoInscriptions {
+    let final_result: Vec<UtxoInscription> = maestro_tx_out_into_response
+        .data
+        .inscriptions
+        .into_iter()
+        .map(|inscription| {
+            let enrichment = enrichments.inscriptions.get(&inscription.inscription_id);
+            UtxoInscription {
+                collection_symbol: enrichment
+                    .and_then(|enrichment| enrichment.collection_symbol.clone()),
+                omb_color: enrichment.and_then(|enrichment| enrichment.omb_color.clone()),
+                omb_floor_price: enrichment.and_then(|enrichment| enrichment.omb_floor_price),
+                inscription_id: inscription.inscription_id,
+            }
+        })
+        .collect();
+
+    let runes: Vec<UtxoRune> = maestro_tx_out_into_response
+        .data
+        .runes
+        .into_iter()
+        .map(|rune| {
+            let enrichment = enrichments.runes.get(&rune.rune_id);
+            let metadata = enrichment.and_then(|enrichment| enrichment.metadata.as_ref());
+            UtxoRune {
+                spaced_name: metadata.map(|metadata| metadata.spaced_name.clone()),
+                symbol: metadata.and_then(|metadata| metadata.symbol.clone()),
+                divisibility: metadata.map(|metadata| metadata.divisibility),
+                market_data: enrichment.and_then(|enrichment| enrichment.market_data.clone()),
+                rune_id: rune.rune_id,
+                amount: rune.amount,
+            }
+        })
+        .collect();
+
+    UtxoInscriptions {
+        data: final_result,
+        runes,
+        last_updated: maestro_tx_out_into_response.last_updated,
+        next_cursor: maestro_tx_out_into_response.next_cursor,
     }
 }
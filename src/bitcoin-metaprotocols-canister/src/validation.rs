@@ -0,0 +1,26 @@
+use crate::error::CanisterError;
+
+// Length of a transaction id in hex
+const TXID_HEX_LEN: usize = 64;
+
+/// Checks that `txid` is a transaction id: 64 hexadecimal characters.
+pub fn validate_txid(txid: &str) -> Result<(), CanisterError> {
+    if txid.len() != TXID_HEX_LEN || !txid.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(CanisterError::InvalidInput(format!(
+            "tx_hash must be {} hexadecimal characters",
+            TXID_HEX_LEN
+        )));
+    }
+    Ok(())
+}
+
+/// Checks that `count` is between 1 and `max`.
+pub fn validate_count(count: u32, max: u32) -> Result<(), CanisterError> {
+    if !(1..=max).contains(&count) {
+        return Err(CanisterError::InvalidInput(format!(
+            "count must be between 1 and {}",
+            max
+        )));
+    }
+    Ok(())
+}